@@ -0,0 +1,265 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! Player accounts. A player signs in under a name, which
+//! may be protected by a password, and the account keeps
+//! their record across games. Accounts are read from the
+//! configured account file at startup and written back
+//! whenever one changes.
+//!
+//! The account file is text, one account per line: `NAME
+//! SALT HASH GAMES ESCAPES KILLS`. `HASH` is the hex
+//! PBKDF2-HMAC-SHA256 of the password with the hex `SALT`,
+//! over [HASH_ROUNDS] rounds; `SALT` and `HASH` are both `-`
+//! for an account without a password. Blank lines and `#`
+//! comments are ignored.
+
+use crate::*;
+
+use sha2::Sha256;
+
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// The server's accounts, by name.
+static ACCOUNTS: OnceLock<Mutex<BTreeMap<String, Account>>> = OnceLock::new();
+
+/// One player's account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    /// Name the player signs in with.
+    pub name: String,
+    /// Salt and password hash, if the account has a
+    /// password.
+    password: Option<(String, String)>,
+    /// Games played.
+    pub games: u64,
+    /// Games in which this player's hero escaped.
+    pub escapes: u64,
+    /// Monsters slain by this player's hero.
+    pub kills: u64,
+}
+
+/// One hero's part in a game, for their account.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Tally {
+    /// The hero escaped.
+    pub escaped: bool,
+    /// Monsters the hero slew.
+    pub kills: u64,
+}
+
+impl Tally {
+    /// Count the hero's part in the given events from their
+    /// own action.
+    pub fn count(&mut self, events: &[GameEvent]) {
+        for event in events {
+            match event {
+                GameEvent::Killed(_) | GameEvent::BossKilled => self.kills += 1,
+                GameEvent::Blasted(count) => self.kills += *count as u64,
+                GameEvent::Escaped | GameEvent::Won => self.escaped = true,
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Rounds of HMAC-SHA256 in a password hash, to make
+/// guessing passwords from a stolen account file slow.
+const HASH_ROUNDS: u32 = 100_000;
+
+/// Hex text for the given bytes.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hex PBKDF2-HMAC-SHA256 of `password` with `salt`.
+fn hash_password(salt: &str, password: &str) -> String {
+    let mut hash = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt.as_bytes(), HASH_ROUNDS, &mut hash);
+    hex(&hash)
+}
+
+/// A fresh salt and the hash of `password` with it. The
+/// salt comes from the operating system's random source.
+fn new_password(password: &str) -> io::Result<(String, String)> {
+    let mut salt = [0u8; 16];
+    getrandom::fill(&mut salt).map_err(io::Error::other)?;
+    let salt = hex(&salt);
+    let hash = hash_password(&salt, password);
+    Ok((salt, hash))
+}
+
+impl Account {
+    /// Does the account have a password?
+    pub fn has_password(&self) -> bool {
+        self.password.is_some()
+    }
+
+    /// Short summary of the player's record.
+    pub fn summary(&self) -> String {
+        format!(
+            "{} games, {} escapes, {} kills",
+            self.games, self.escapes, self.kills,
+        )
+    }
+
+    /// Account file line for this account, without the
+    /// newline.
+    fn format(&self) -> String {
+        let (salt, hash) = match &self.password {
+            Some((salt, hash)) => (salt.as_str(), hash.as_str()),
+            None => ("-", "-"),
+        };
+        format!(
+            "{} {} {} {} {} {}",
+            self.name, salt, hash, self.games, self.escapes, self.kills,
+        )
+    }
+
+    /// Parse an account file line.
+    fn parse(line: &str) -> Result<Account, String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [name, salt, hash, games, escapes, kills] = fields[..] else {
+            return Err(format!("expected 6 fields, got {}", fields.len()));
+        };
+        let password = match (salt, hash) {
+            ("-", "-") => None,
+            (salt, hash) => Some((salt.to_string(), hash.to_string())),
+        };
+        Ok(Account {
+            name: name.to_string(),
+            password,
+            games: number(games)?,
+            escapes: number(escapes)?,
+            kills: number(kills)?,
+        })
+    }
+}
+
+/// Read the accounts from the given file, and make them the
+/// server's accounts. A missing file holds no accounts.
+/// Fails if the file is bad or accounts are already
+/// installed.
+pub fn install_accounts(path: &Path) -> io::Result<()> {
+    let text = read_or_empty(path)?;
+    let mut accounts = BTreeMap::new();
+    for (lineno, line) in content_lines(&text) {
+        let account = Account::parse(line).map_err(|e| bad(path.display(), lineno, &e))?;
+        accounts.insert(account.name.clone(), account);
+    }
+    ACCOUNTS
+        .set(Mutex::new(accounts))
+        .map_err(|_| io::Error::other("accounts already installed"))
+}
+
+/// Execute some code on the server's accounts under their
+/// lock. With no accounts installed there are none.
+fn with_accounts<T>(action: impl FnOnce(&mut BTreeMap<String, Account>) -> T) -> T {
+    let accounts = ACCOUNTS.get_or_init(Mutex::default);
+    action(&mut accounts.lock().unwrap())
+}
+
+/// Write the accounts back to the configured account file.
+fn save_accounts(accounts: &BTreeMap<String, Account>) -> io::Result<()> {
+    let path = &config().account_file;
+    let mut text = String::from("# one-way-out accounts\n");
+    for account in accounts.values() {
+        text += &account.format();
+        text.push('\n');
+    }
+    replace_file(path, &text)
+}
+
+/// The account with the given name, if any.
+pub fn account(name: &str) -> Option<Account> {
+    with_accounts(|accounts| accounts.get(name).cloned())
+}
+
+/// Do two hashes match? Every byte is looked at, so that
+/// the time taken does not tell where they differ.
+fn same_hash(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+/// Is `password` right for the named account? Always true
+/// for an account without a password. The slow hash is done
+/// outside the accounts lock.
+pub fn check_password(name: &str, password: &str) -> bool {
+    let password_of = |accounts: &mut BTreeMap<String, Account>| {
+        accounts.get(name).map(|account| account.password.clone())
+    };
+    match with_accounts(password_of) {
+        Some(Some((salt, hash))) => same_hash(&hash_password(&salt, password), &hash),
+        Some(None) => true,
+        None => false,
+    }
+}
+
+/// Make a new account with the given name and, if given, a
+/// password. Returns `false`, leaving the accounts alone, if
+/// an account with that name was made meanwhile. The account
+/// is kept for this run of the server even if it cannot be
+/// saved.
+pub fn create_account(name: &str, password: Option<&str>) -> io::Result<bool> {
+    let password = password.map(new_password).transpose()?;
+    let account = Account {
+        name: name.to_string(),
+        password,
+        games: 0,
+        escapes: 0,
+        kills: 0,
+    };
+    with_accounts(|accounts| {
+        if accounts.contains_key(name) {
+            return Ok(false);
+        }
+        accounts.insert(name.to_string(), account);
+        save_accounts(accounts)?;
+        Ok(true)
+    })
+}
+
+/// Add a game to the named account's record.
+pub fn record_game(name: &str, tally: Tally) -> io::Result<()> {
+    with_accounts(|accounts| {
+        let Some(account) = accounts.get_mut(name) else {
+            return Ok(());
+        };
+        account.games += 1;
+        if tally.escaped {
+            account.escapes += 1;
+        }
+        account.kills += tally.kills;
+        save_accounts(accounts)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwords_are_salted() {
+        let (salt, hash) = new_password("hunter2").unwrap();
+        let (other_salt, other_hash) = new_password("hunter2").unwrap();
+        assert_ne!(salt, other_salt);
+        assert_ne!(hash, other_hash);
+        assert_eq!(hash_password(&salt, "hunter2"), hash);
+        assert_ne!(hash_password(&salt, "hunter3"), hash);
+    }
+
+    #[test]
+    fn hashes_match_exactly() {
+        assert!(same_hash("0a1b", "0a1b"));
+        assert!(!same_hash("0a1b", "0a1c"));
+        assert!(!same_hash("0a1b", "0a1"));
+        assert!(!same_hash("", "0"));
+    }
+}
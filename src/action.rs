@@ -0,0 +1,93 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! Player actions, and the game events they produce. These
+//! are the whole interface between a client and the game
+//! rules: see [crate::Game::apply].
+
+use crate::Item;
+
+/// Direction of movement along the dungeon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dir {
+    /// Back toward the entrance.
+    Left,
+    /// On toward the door.
+    Right,
+}
+
+impl Dir {
+    /// Field offset of a one-tile step in this direction.
+    pub fn offset(self) -> isize {
+        match self {
+            Dir::Left => -1,
+            Dir::Right => 1,
+        }
+    }
+}
+
+/// Something a player avatar can do on its turn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Step (or attack) in the given direction.
+    Move(Dir),
+    /// Rest, maybe regaining health.
+    Rest,
+    /// Pick up the item underfoot into the party's slot,
+    /// leaving any item already there in exchange.
+    PickUp,
+    /// Drop the party's item underfoot.
+    Drop,
+    /// Use the party's item.
+    Use,
+    /// Leave the game.
+    Quit,
+}
+
+/// Something that happened as a result of an action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameEvent {
+    /// The player moved to the given field position.
+    Moved(usize),
+    /// The player's way was blocked.
+    Blocked,
+    /// The player tried the door while the boss lives.
+    Guarded,
+    /// The player hit the given monster, which survived.
+    Hit(u64),
+    /// The player killed the given monster.
+    Killed(u64),
+    /// The player killed the boss.
+    BossKilled,
+    /// The player rested, healing this much.
+    Rested(u64),
+    /// The party took this much damage from monsters.
+    Damaged(u64),
+    /// The player picked up this item.
+    PickedUp(Item),
+    /// The player dropped this item.
+    Dropped(Item),
+    /// The player used this item.
+    Used(Item),
+    /// The player equipped the party with this item.
+    Equipped(Item),
+    /// The player drank a potion, healing this much.
+    Healed(u64),
+    /// The player read a scroll, blasting this many
+    /// monsters.
+    Blasted(usize),
+    /// There was no item to pick up, drop or use.
+    NoItem,
+    /// The player quit the game.
+    Quit,
+    /// The player escaped through the door.
+    Escaped,
+    /// The last hero escaped: the game is won.
+    Won,
+    /// The party's health ran out: the game is lost.
+    Wiped,
+    /// The last hero quit: the game is over.
+    Abandoned,
+}
@@ -0,0 +1,289 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! Monster AI. Each [Behaviour] named in the monster table
+//! has an [Ai] that decides where a monster of that kind
+//! wants to move, looking at the world through read-only
+//! [Senses].
+
+use crate::*;
+
+/// How far a monster can see, in tiles.
+const SIGHT: usize = 10;
+
+/// How far a guard strays from its post, in tiles.
+const GUARD_RANGE: usize = 3;
+
+/// Pack monsters this far apart count as one pack.
+const PACK_RANGE: usize = 4;
+
+/// A pack this big or bigger goes hunting.
+const PACK_HUNT: usize = 3;
+
+/// What a monster can know when choosing its move.
+pub struct Senses<'a> {
+    /// The playfield.
+    pub field: &'a Field,
+    /// Positions of the heroes.
+    pub heroes: Vec<usize>,
+    /// All the monsters.
+    pub monsters: &'a BTreeMap<u64, Mob>,
+}
+
+impl Senses<'_> {
+    /// Position of the hero nearest `posn` within sight, if
+    /// any.
+    pub fn nearest_hero(&self, posn: usize) -> Option<usize> {
+        self.heroes
+            .iter()
+            .copied()
+            .filter(|hero| hero.abs_diff(posn) <= SIGHT)
+            .min_by_key(|hero| hero.abs_diff(posn))
+    }
+}
+
+/// A way of choosing monster moves.
+pub trait Ai: Sync {
+    /// Where `mob` wants to move: its own position, or
+    /// one tile either side. The move may yet be blocked.
+    fn get_move(&self, mob: &Mob, senses: &Senses, rng: &mut Rng) -> usize;
+}
+
+/// One step from `posn` toward `target`.
+fn toward(posn: usize, target: usize) -> usize {
+    match target.cmp(&posn) {
+        std::cmp::Ordering::Less => posn - 1,
+        std::cmp::Ordering::Equal => posn,
+        std::cmp::Ordering::Greater => posn + 1,
+    }
+}
+
+/// One step from `posn` away from `target`.
+fn away(posn: usize, target: usize) -> usize {
+    if target > posn {
+        posn.saturating_sub(1)
+    } else {
+        posn + 1
+    }
+}
+
+/// Stagger about at random.
+struct Wander;
+
+impl Ai for Wander {
+    fn get_move(&self, mob: &Mob, _senses: &Senses, rng: &mut Rng) -> usize {
+        let posn = mob.posn;
+        match random(rng, 3) {
+            0 => posn.saturating_sub(1),
+            1 => posn,
+            _ => posn + 1,
+        }
+    }
+}
+
+/// Head for the nearest hero in sight, else wander.
+struct Chase;
+
+impl Ai for Chase {
+    fn get_move(&self, mob: &Mob, senses: &Senses, rng: &mut Rng) -> usize {
+        match senses.nearest_hero(mob.posn) {
+            Some(hero) => toward(mob.posn, hero),
+            None => Wander.get_move(mob, senses, rng),
+        }
+    }
+}
+
+/// Chase until badly wounded, then run from the nearest
+/// hero.
+struct Flee;
+
+impl Ai for Flee {
+    fn get_move(&self, mob: &Mob, senses: &Senses, rng: &mut Rng) -> usize {
+        if mob.health() * 2 > mob.max_health() {
+            return Chase.get_move(mob, senses, rng);
+        }
+        match senses.nearest_hero(mob.posn) {
+            Some(hero) => away(mob.posn, hero),
+            None => mob.posn,
+        }
+    }
+}
+
+/// Stay near home, going after heroes that come close.
+struct Guard;
+
+impl Ai for Guard {
+    fn get_move(&self, mob: &Mob, senses: &Senses, _rng: &mut Rng) -> usize {
+        let target = senses
+            .nearest_hero(mob.posn)
+            .filter(|hero| hero.abs_diff(mob.home) <= GUARD_RANGE)
+            .unwrap_or(mob.home);
+        toward(mob.posn, target)
+    }
+}
+
+/// Keep together with others of the same kind, and hunt
+/// heroes once the pack is big enough.
+struct Pack;
+
+impl Ai for Pack {
+    fn get_move(&self, mob: &Mob, senses: &Senses, rng: &mut Rng) -> usize {
+        let pack: Vec<usize> = senses
+            .monsters
+            .values()
+            .filter(|m| std::ptr::eq(m.kind, mob.kind))
+            .map(|m| m.posn)
+            .filter(|posn| posn.abs_diff(mob.posn) <= PACK_RANGE)
+            .collect();
+        if pack.len() >= PACK_HUNT {
+            return Chase.get_move(mob, senses, rng);
+        }
+        // Drift toward the middle of the pack, or wander if
+        // already there or alone.
+        let middle = pack.iter().sum::<usize>() / pack.len();
+        if middle == mob.posn {
+            return Wander.get_move(mob, senses, rng);
+        }
+        toward(mob.posn, middle)
+    }
+}
+
+impl Behaviour {
+    /// The AI for this behaviour.
+    pub fn ai(self) -> &'static dyn Ai {
+        match self {
+            Behaviour::Wander => &Wander,
+            Behaviour::Chase => &Chase,
+            Behaviour::Flee => &Flee,
+            Behaviour::Guard => &Guard,
+            Behaviour::Pack => &Pack,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A monster kind of the given behaviour whose members
+    /// all start with the given health.
+    fn kind(ai: Behaviour, health: u64) -> &'static MonsterKind {
+        Box::leak(Box::new(MonsterKind {
+            name: "test".to_string(),
+            glyph: 't',
+            min_health: health,
+            max_health: health,
+            damage: 1,
+            speed: 100,
+            ai,
+            depth: 0,
+        }))
+    }
+
+    /// Where a monster of `kind` at `posn`, having taken
+    /// `wounds`, wants to go with heroes at `heroes` and
+    /// `others` of its kind about.
+    fn get_move(
+        kind: &'static MonsterKind,
+        posn: usize,
+        wounds: u64,
+        heroes: &[usize],
+        others: &[usize],
+    ) -> usize {
+        let mut rng = Rng::with_seed(1);
+        let mut mob = Mob::new(0, posn, kind, &mut rng);
+        mob.hit(wounds);
+        let mut monsters: BTreeMap<u64, Mob> = others
+            .iter()
+            .enumerate()
+            .map(|(i, &posn)| (i as u64 + 1, Mob::new(i as u64 + 1, posn, kind, &mut rng)))
+            .collect();
+        monsters.insert(0, mob);
+        let field = Field::default();
+        let senses = Senses {
+            field: &field,
+            heroes: heroes.to_vec(),
+            monsters: &monsters,
+        };
+        kind.ai.ai().get_move(&monsters[&0], &senses, &mut rng)
+    }
+
+    #[test]
+    fn chase_heads_for_the_nearest_hero() {
+        let chase = kind(Behaviour::Chase, 10);
+        // (monster, heroes, move)
+        let table: [(usize, &[usize], usize); 4] = [
+            (20, &[25], 21),
+            (20, &[15], 19),
+            (20, &[14, 23], 21),
+            (20, &[20 + SIGHT], 21),
+        ];
+        for (posn, heroes, want) in table {
+            assert_eq!(get_move(chase, posn, 0, heroes, &[]), want, "{:?}", heroes);
+        }
+        // Out of sight, it wanders.
+        let posn = get_move(chase, 50, 0, &[50 + SIGHT + 1], &[]);
+        assert!(posn.abs_diff(50) <= 1);
+    }
+
+    #[test]
+    fn flee_runs_once_badly_wounded() {
+        let flee = kind(Behaviour::Flee, 10);
+        // (wounds, heroes, move)
+        let table: [(u64, &[usize], usize); 6] = [
+            (0, &[25], 21),
+            (4, &[25], 21),
+            (5, &[25], 19),
+            (8, &[25], 19),
+            (8, &[15], 21),
+            (8, &[], 20),
+        ];
+        for (wounds, heroes, want) in table {
+            assert_eq!(get_move(flee, 20, wounds, heroes, &[]), want, "{}", wounds);
+        }
+    }
+
+    #[test]
+    fn guard_keeps_to_its_post() {
+        let guard = kind(Behaviour::Guard, 10);
+        let mut rng = Rng::with_seed(1);
+        let home = 20;
+        // (monster, heroes, move)
+        let table: [(usize, &[usize], usize); 5] = [
+            (home, &[home + GUARD_RANGE], home + 1),
+            (home, &[home + GUARD_RANGE + 1], home),
+            (home + 2, &[home + GUARD_RANGE + 2], home + 1),
+            (home, &[], home),
+            (home + 1, &[], home),
+        ];
+        for (posn, heroes, want) in table {
+            let mut mob = Mob::new(0, home, guard, &mut rng);
+            mob.posn = posn;
+            let monsters = BTreeMap::from([(0, mob)]);
+            let field = Field::default();
+            let senses = Senses {
+                field: &field,
+                heroes: heroes.to_vec(),
+                monsters: &monsters,
+            };
+            let got = guard.ai.ai().get_move(&monsters[&0], &senses, &mut rng);
+            assert_eq!(got, want, "{} {:?}", posn, heroes);
+            assert!(got.abs_diff(home) <= GUARD_RANGE);
+        }
+    }
+
+    #[test]
+    fn packs_gather_then_hunt() {
+        let pack = kind(Behaviour::Pack, 10);
+        // Too few to hunt: drift to the middle of the pack.
+        assert_eq!(get_move(pack, 20, 0, &[15], &[24]), 21);
+        assert_eq!(get_move(pack, 20, 0, &[25], &[16]), 19);
+        // Big enough: hunt the nearest hero.
+        assert_eq!(get_move(pack, 20, 0, &[15], &[22, 24]), 19);
+        // Packmates out of range do not count.
+        let far = 20 + PACK_RANGE + 1;
+        assert_eq!(get_move(pack, 20, 0, &[15], &[22, far]), 21);
+    }
+}
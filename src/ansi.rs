@@ -0,0 +1,212 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! Display for clients with ANSI terminals. The board goes
+//! in color on the top row with the status display below it
+//! and the current message below that. The rest of the
+//! screen is a scrolling region for printed text. The cursor
+//! is placed directly, so nothing need be re-sent just to
+//! move it.
+
+use crate::*;
+
+/// Screen row of the board.
+const BOARD_ROW: u16 = 1;
+
+/// Screen row of the status display.
+const STATUS_ROW: u16 = 2;
+
+/// Screen row of the current message.
+const MESSAGE_ROW: u16 = 3;
+
+/// Fewest rows the ANSI display needs: the fixed rows and
+/// one for printed text.
+const MIN_ROWS: u16 = MESSAGE_ROW + 1;
+
+/// SGR parameters for each kind of thing on the board.
+const ROCK: &str = "0;37";
+const FLOOR: &str = "0;2";
+const HERO: &str = "0;32";
+const SELF: &str = "0;1;32";
+const DOOR: &str = "0;1;33";
+const MONSTER: &str = "0;31";
+const BOSS: &str = "0;1;35";
+const ITEM: &str = "0;36";
+
+/// Turn off all character attributes.
+const RESET: &str = "\x1b[0m";
+
+/// SGR parameters for a board tile. `own` is set for the
+/// player's own hero.
+fn color(tile: Tile, own: bool) -> &'static str {
+    match tile {
+        Tile::Hero if own => SELF,
+        Tile::Hero => HERO,
+        Tile::Rock => ROCK,
+        Tile::Floor | Tile::Blank => FLOOR,
+        Tile::Door => DOOR,
+        Tile::Boss => BOSS,
+        Tile::Monster => MONSTER,
+        Tile::Item => ITEM,
+    }
+}
+
+/// The board `line` colored for display by its `tiles`,
+/// with the hero at column `cursor` picked out.
+fn paint(line: &str, tiles: &[Tile], cursor: usize) -> String {
+    let mut painted = String::new();
+    let mut current = "";
+    for (i, c) in line.chars().enumerate() {
+        let tile = tiles.get(i).copied().unwrap_or(Tile::Blank);
+        let color = color(tile, i == cursor);
+        if color != current {
+            painted.push_str(&format!("\x1b[{}m", color));
+            current = color;
+        }
+        painted.push(c);
+    }
+    painted.push_str(RESET);
+    painted
+}
+
+/// What an ANSI client is currently showing.
+pub struct AnsiScreen {
+    /// Where the status display goes.
+    area: StatusArea,
+    /// Terminal width.
+    width: usize,
+    /// Terminal height.
+    height: u16,
+    /// Board row last sent, with its colors.
+    line: String,
+    /// Status last sent.
+    status: String,
+    /// Message last sent.
+    message: String,
+    /// Cursor column last sent.
+    cursor: usize,
+    /// The screen has been written over and must be
+    /// redrawn.
+    stale: bool,
+}
+
+impl AnsiScreen {
+    /// An ANSI screen for the given client, with the status
+    /// display in the given area, set up and cleared. `None`
+    /// if the client has no ANSI terminal, or one too small.
+    pub fn new(remote: &mut Connection, area: StatusArea) -> Option<Self> {
+        let height = remote.height.filter(|&height| height >= MIN_ROWS)?;
+        if !remote.ansi || remote.line_mode {
+            return None;
+        }
+        // Clear the screen and scroll only below the fixed
+        // rows.
+        let _ = write!(remote, "\x1b[2J\x1b[{};{}r", MIN_ROWS, height);
+        Some(AnsiScreen {
+            area,
+            width: remote.width.unwrap_or(Player::WIDTH) as usize,
+            height,
+            line: String::new(),
+            status: String::new(),
+            message: String::new(),
+            cursor: 0,
+            stale: true,
+        })
+    }
+
+    /// Put the cursor at column `cursor` of the board row.
+    fn place_cursor(&self, remote: &mut Connection) {
+        let _ = write!(remote, "\x1b[{};{}H", BOARD_ROW, self.cursor + 1);
+    }
+}
+
+impl Render for AnsiScreen {
+    fn area(&self) -> StatusArea {
+        self.area
+    }
+
+    fn draw(
+        &mut self,
+        remote: &mut Connection,
+        showing: Showing,
+        status: &str,
+        cursor: usize,
+        _acted: bool,
+    ) {
+        let (line, message) = match showing {
+            Showing::Board(line, tiles, message) => {
+                (paint(&line, &tiles, cursor), message.unwrap_or(""))
+            }
+            Showing::Text(text) => (text, ""),
+        };
+        let width = self.width;
+        let mut changed = self.stale || cursor != self.cursor;
+        if self.stale || line != self.line {
+            let _ = write!(remote, "\x1b[{};1H{}\x1b[K", BOARD_ROW, line);
+            self.line = line;
+            changed = true;
+        }
+        if self.stale || status != self.status {
+            let _ = write!(
+                remote,
+                "\x1b[{};1H\x1b[7m{:.width$}{}\x1b[K",
+                STATUS_ROW, status, RESET,
+            );
+            self.status = status.to_string();
+            changed = true;
+        }
+        if self.stale || message != self.message {
+            let _ = write!(
+                remote,
+                "\x1b[{};1H\x1b[1m{:.width$}{}\x1b[K",
+                MESSAGE_ROW, message, RESET,
+            );
+            self.message = message.to_string();
+            changed = true;
+        }
+        self.cursor = cursor;
+        self.stale = false;
+        if changed {
+            self.place_cursor(remote);
+        }
+    }
+
+    fn print(&mut self, remote: &mut Connection, text: &str) {
+        // A new line at the bottom scrolls the text region.
+        let width = self.width;
+        let _ = write!(remote, "\x1b[{};1H\r\n{:.width$}", self.height, text);
+        self.place_cursor(remote);
+    }
+
+    fn finish(&mut self, remote: &mut Connection, text: &str) {
+        let width = self.width;
+        let _ = write!(
+            remote,
+            "\x1b[{};1H\x1b[1m{:.width$}{}\x1b[K",
+            MESSAGE_ROW, text, RESET,
+        );
+        // Give the whole screen back for scrolling, and
+        // leave the cursor on a fresh line at the bottom.
+        let _ = write!(remote, "\x1b[r\x1b[{};1H\r\n", self.height);
+        self.forget();
+    }
+
+    fn forget(&mut self) {
+        self.stale = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_follow_tiles_not_glyphs() {
+        let tiles = [Tile::Item, Tile::Monster, Tile::Hero];
+        let painted = paint("kk@", &tiles, 2);
+        let expected = format!("\x1b[{}mk\x1b[{}mk\x1b[{}m@{}", ITEM, MONSTER, SELF, RESET);
+        assert_eq!(painted, expected);
+    }
+}
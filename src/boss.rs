@@ -0,0 +1,41 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! The Boss: one big monster guarding the door at the end.
+//! It never moves, and the door cannot be entered until it
+//! is dead. Its attacks are in [crate::Game::monster_attacks].
+
+use crate::*;
+
+impl Game {
+    /// Put the boss just short of the door. There is no boss
+    /// if the door is too close to the entrance to leave
+    /// room for one.
+    pub fn place_boss(&mut self) {
+        let posn = config().door_posn - 1;
+        if posn < 2 {
+            return;
+        }
+        let id = self.next_monster_id;
+        self.next_monster_id += 1;
+        let mob = Mob::boss(id, posn, &mut self.rng);
+        self.field[posn].object = Some(mob.object());
+        self.monsters.insert(id, mob);
+        self.boss = Some(id);
+    }
+
+    /// Boss health and starting health, if the boss lives
+    /// and is in the given player's view.
+    pub fn boss_status(&self, player_id: u64) -> Option<(u64, u64)> {
+        let boss = &self.monsters[&self.boss?];
+        let player = self.players.get(&player_id)?;
+        let left = player.posn - player.left;
+        let right = left + player.width as usize;
+        if boss.posn < left || boss.posn >= right {
+            return None;
+        }
+        Some((boss.health(), boss.max_health()))
+    }
+}
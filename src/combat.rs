@@ -0,0 +1,103 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! Combat resolution: heroes hitting monsters, and monsters
+//! hitting the party. The party's equipment modifies the
+//! damage both ways, and is applied only here. The boss
+//! has an attack pattern of its own.
+
+use crate::*;
+
+/// Extra damage dealt by a hero when the party wields a
+/// weapon.
+const WEAPON_BONUS: u64 = 2;
+
+/// When the party wears armor, one in this many monster
+/// blows is turned aside.
+const ARMOR_ODDS: u64 = 2;
+
+/// The boss strikes every hero up to this many tiles away.
+const BOSS_REACH: usize = 2;
+
+/// Once per this many turns the boss slams instead of
+/// striking.
+const BOSS_SLAM_TURNS: u64 = 4;
+
+/// Strength of a boss slam.
+const BOSS_SLAM: u64 = 5;
+
+impl Game {
+    /// Damage dealt by one blow from a hero.
+    fn hero_damage(&mut self) -> u64 {
+        let damage = random(&mut self.rng, 3);
+        match self.weapon {
+            Some(_) => damage + WEAPON_BONUS,
+            None => damage,
+        }
+    }
+
+    /// Damage taken by the party from one monster blow of
+    /// the given strength.
+    fn party_damage(&mut self, strength: u64) -> u64 {
+        if self.armor.is_some() && random(&mut self.rng, ARMOR_ODDS) == 0 {
+            return 0;
+        }
+        strength
+    }
+
+    /// A hero attacks the given monster, at the given
+    /// position.
+    pub fn attack(&mut self, mob_id: u64, posn: usize) -> GameEvent {
+        let damage = self.hero_damage();
+        let mob = self.monsters.get_mut(&mob_id).unwrap();
+        if mob.hit(damage) {
+            return GameEvent::Hit(mob_id);
+        }
+        // Killed the monster.
+        self.kills += 1;
+        self.monsters.remove(&mob_id);
+        self.field[posn].object = None;
+        if self.boss == Some(mob_id) {
+            self.boss = None;
+            return GameEvent::BossKilled;
+        }
+        self.leave_loot(posn);
+        GameEvent::Killed(mob_id)
+    }
+
+    /// Monsters next to heroes attack them, and the boss
+    /// attacks every hero within its reach. Away heroes are
+    /// not attacked. Returns the damage done to the party.
+    pub fn monster_attacks(&mut self) -> u64 {
+        let boss_posn = self.boss.map(|id| self.monsters[&id].posn);
+        let boss_strength = if self.turns.is_multiple_of(BOSS_SLAM_TURNS) {
+            BOSS_SLAM
+        } else {
+            boss_kind().damage
+        };
+        let mut blows = Vec::new();
+        for p in self.present() {
+            for posn in [p.posn - 1, p.posn + 1] {
+                if let Some(Object::Monster(id, _)) = self.field[posn].object
+                    && self.boss != Some(id)
+                {
+                    blows.push(self.monsters[&id].kind.damage);
+                }
+            }
+            if let Some(boss_posn) = boss_posn
+                && p.posn.abs_diff(boss_posn) <= BOSS_REACH
+            {
+                blows.push(boss_strength);
+            }
+        }
+        let mut damage = 0;
+        for strength in blows {
+            let blow = self.party_damage(strength).min(self.health);
+            self.health -= blow;
+            damage += blow;
+        }
+        damage
+    }
+}
@@ -0,0 +1,343 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! Server configuration. Settings come from built-in
+//! defaults, then an optional TOML-style config file, then
+//! command-line flags, with later sources overriding
+//! earlier ones. The validated configuration is installed
+//! once at startup and read everywhere through [config()].
+
+use crate::*;
+
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// The installed server configuration.
+static CONFIG: OnceLock<ServerConfig> = OnceLock::new();
+
+/// Get the installed server configuration, or the defaults
+/// if none has been installed.
+pub fn config() -> &'static ServerConfig {
+    CONFIG.get_or_init(ServerConfig::default)
+}
+
+/// Error in server configuration, with a human-readable
+/// description of what went wrong.
+#[derive(Debug)]
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Build a [ConfigError] from format arguments.
+macro_rules! config_error {
+    ($($arg:tt)*) => {
+        ConfigError(format!($($arg)*))
+    };
+}
+
+/// Server and game tuning settings.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Address the telnet listener binds to.
+    pub bind: IpAddr,
+    /// Port the telnet listener binds to.
+    pub port: u16,
+    /// Path of an additional Unix-domain socket listener,
+    /// if any.
+    pub unix: Option<PathBuf>,
+    /// Port of the browser WebSocket gateway, if any.
+    pub web_port: Option<u16>,
+    /// Distance in tiles to the exit door.
+    pub door_posn: usize,
+    /// Maximum (and starting) shared hero health.
+    pub max_health: u64,
+    /// Desired left/right margin on terminal in characters.
+    pub margin: usize,
+    /// At most one monster may be live per this many tiles
+    /// of established field.
+    pub spawn_tiles: usize,
+    /// At most one monster may be live per this many turns
+    /// of elapsed game time.
+    pub spawn_turns: u64,
+    /// A potion or scroll appears once per this many turns
+    /// of elapsed game time.
+    pub item_turns: u64,
+    /// Monster table file, if not the built-in table.
+    pub monsters: Option<PathBuf>,
+    /// Most actions a player may have typed ahead and
+    /// waiting to be taken. More are dropped.
+    pub max_queued: usize,
+    /// Random seed for every new game, to reproduce a
+    /// game. If `None` each game gets a random seed.
+    pub seed: Option<u64>,
+    /// Directory to save game recordings in, if any.
+    pub replay_dir: Option<PathBuf>,
+    /// File keeping the score of every finished game.
+    pub score_file: PathBuf,
+    /// File keeping the player accounts.
+    pub account_file: PathBuf,
+    /// File to save running games in, and resume them from
+    /// at startup, if any.
+    pub save_file: Option<PathBuf>,
+    /// Seconds between saves of the running games.
+    pub save_interval: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: IpAddr::from([0, 0, 0, 0]),
+            port: 10001,
+            unix: None,
+            web_port: None,
+            door_posn: DOOR_POSN,
+            max_health: MAX_HEALTH,
+            margin: Player::MARGIN,
+            spawn_tiles: 20,
+            spawn_turns: 5,
+            item_turns: 40,
+            monsters: None,
+            max_queued: 8,
+            seed: None,
+            replay_dir: None,
+            score_file: PathBuf::from("one-way-out.scores"),
+            account_file: PathBuf::from("one-way-out.accounts"),
+            save_file: None,
+            save_interval: 60,
+        }
+    }
+}
+
+/// Command-line flags that take no value. They are handled
+/// by the caller; [ServerConfig::from_args] skips them.
+pub const SWITCHES: &[&str] = &["help", "scores"];
+
+/// Largest allowed view margin in characters: a quarter of
+/// the default terminal width, so that narrow terminals
+/// still leave the hero room to move.
+const MAX_MARGIN: usize = Player::WIDTH as usize / 4;
+
+/// Largest allowed distance to the exit door in tiles.
+const MAX_DOOR_POSN: usize = 100_000;
+
+/// Command-line usage message.
+pub const USAGE: &str = "\
+usage: one-way-out [options]
+       one-way-out replay FILE [--speed MS]
+       one-way-out --scores [options]
+  --config FILE       read settings from TOML-style FILE
+  --bind ADDR         listen address (default 0.0.0.0)
+  --port N            listen port (default 10001)
+  --unix PATH         also listen on Unix-domain socket PATH
+  --web-port N        also serve browser players on port N
+  --door-posn N       distance to the exit door in tiles
+  --max-health N      shared hero health
+  --margin N          view margin in characters
+  --spawn-tiles N     field tiles per live monster
+  --spawn-turns N     game turns per live monster
+  --item-turns N      game turns per potion or scroll
+  --monsters FILE     read monster kinds from FILE
+  --max-queued N      most actions a player may type ahead
+  --seed N            random seed for every new game
+  --replay-dir DIR    save a replay of each finished game in DIR
+  --score-file FILE   keep game scores in FILE
+                      (default one-way-out.scores)
+  --account-file FILE keep player accounts in FILE
+                      (default one-way-out.accounts)
+  --save-file FILE    save running games in FILE, and resume
+                      them from it at startup
+  --save-interval N   seconds between saves (default 60)
+  --scores            show the leaderboards and exit
+  --help              show this message";
+
+/// Parse a numeric setting value.
+fn parse_num<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, ConfigError> {
+    number(value).map_err(|e| config_error!("{}: {}", key, e))
+}
+
+impl ServerConfig {
+    /// Set the setting named `key` from the text `value`.
+    /// Keys use the config-file spelling (`door_posn`).
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "bind" => {
+                self.bind = value
+                    .parse()
+                    .map_err(|_| config_error!("bind: invalid address \"{}\"", value))?;
+            }
+            "port" => self.port = parse_num(key, value)?,
+            "unix" => self.unix = Some(PathBuf::from(value)),
+            "web_port" => self.web_port = Some(parse_num(key, value)?),
+            "door_posn" => self.door_posn = parse_num(key, value)?,
+            "max_health" => self.max_health = parse_num(key, value)?,
+            "margin" => self.margin = parse_num(key, value)?,
+            "spawn_tiles" => self.spawn_tiles = parse_num(key, value)?,
+            "spawn_turns" => self.spawn_turns = parse_num(key, value)?,
+            "item_turns" => self.item_turns = parse_num(key, value)?,
+            "monsters" => self.monsters = Some(PathBuf::from(value)),
+            "max_queued" => self.max_queued = parse_num(key, value)?,
+            "seed" => self.seed = Some(parse_num(key, value)?),
+            "replay_dir" => self.replay_dir = Some(PathBuf::from(value)),
+            "score_file" => self.score_file = PathBuf::from(value),
+            "account_file" => self.account_file = PathBuf::from(value),
+            "save_file" => self.save_file = Some(PathBuf::from(value)),
+            "save_interval" => self.save_interval = parse_num(key, value)?,
+            _ => return Err(config_error!("unknown setting \"{}\"", key)),
+        }
+        Ok(())
+    }
+
+    /// Read settings from a TOML-style file of `key = value`
+    /// lines. Blank lines, `#` comments and `[section]`
+    /// headers are ignored; string values may be quoted.
+    pub fn load_file(&mut self, path: &str) -> Result<(), ConfigError> {
+        let text =
+            fs::read_to_string(path).map_err(|e| config_error!("{}: cannot read: {}", path, e))?;
+        for (lineno, line) in content_lines(&text) {
+            if line.starts_with('[') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| config_error!("{}:{}: expected key = value", path, lineno + 1))?;
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            self.set(key.trim(), value)
+                .map_err(|e| config_error!("{}:{}: {}", path, lineno + 1, e))?;
+        }
+        Ok(())
+    }
+
+    /// Build a configuration from the given command-line
+    /// arguments (without the program name). A `--config`
+    /// file is read first so that other flags override it.
+    /// The value-less [SWITCHES] are skipped.
+    pub fn from_args<I>(args: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut flags = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let key = arg
+                .strip_prefix("--")
+                .ok_or_else(|| config_error!("unexpected argument \"{}\"\n{}", arg, USAGE))?
+                .replace('-', "_");
+            if SWITCHES.contains(&key.as_str()) {
+                continue;
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| config_error!("--{}: missing value", key.replace('_', "-")))?;
+            flags.push((key, value));
+        }
+
+        let mut config = ServerConfig::default();
+        for (key, value) in &flags {
+            if key == "config" {
+                config.load_file(value)?;
+            }
+        }
+        for (key, value) in flags.iter().filter(|(key, _)| key != "config") {
+            config
+                .set(key, value)
+                .map_err(|e| config_error!("command line: {}", e))?;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check that the settings are usable together.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.port == 0 {
+            return Err(config_error!("port: must be nonzero"));
+        }
+        if let Some(web_port) = self.web_port {
+            if web_port == 0 {
+                return Err(config_error!("web_port: must be nonzero"));
+            }
+            if web_port == self.port {
+                return Err(config_error!("web_port: must differ from port"));
+            }
+        }
+        if self.door_posn < 2 {
+            return Err(config_error!("door_posn: must be at least 2"));
+        }
+        if self.door_posn > MAX_DOOR_POSN {
+            return Err(config_error!(
+                "door_posn: must be at most {}",
+                MAX_DOOR_POSN
+            ));
+        }
+        if self.max_health == 0 {
+            return Err(config_error!("max_health: must be positive"));
+        }
+        if self.margin == 0 {
+            return Err(config_error!("margin: must be positive"));
+        }
+        if self.margin > MAX_MARGIN {
+            return Err(config_error!("margin: must be at most {}", MAX_MARGIN));
+        }
+        if self.spawn_tiles == 0 {
+            return Err(config_error!("spawn_tiles: must be positive"));
+        }
+        if self.spawn_turns == 0 {
+            return Err(config_error!("spawn_turns: must be positive"));
+        }
+        if self.item_turns == 0 {
+            return Err(config_error!("item_turns: must be positive"));
+        }
+        if self.max_queued == 0 {
+            return Err(config_error!("max_queued: must be positive"));
+        }
+        if self.save_interval == 0 {
+            return Err(config_error!("save_interval: must be positive"));
+        }
+        Ok(())
+    }
+
+    /// Make this the configuration returned by [config()].
+    /// Fails if a configuration is already installed.
+    pub fn install(self) -> Result<(), ConfigError> {
+        CONFIG
+            .set(self)
+            .map_err(|_| config_error!("configuration already installed"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn switches_take_no_value() {
+        let config = ServerConfig::from_args(args(&["--scores", "--port", "10002"])).unwrap();
+        assert_eq!(config.port, 10002);
+        assert!(ServerConfig::from_args(args(&["--port", "10002", "--help"])).is_ok());
+    }
+
+    #[test]
+    fn settings_are_bounded() {
+        assert!(ServerConfig::from_args(args(&["--margin", "21"])).is_err());
+        assert!(ServerConfig::from_args(args(&["--margin", "20"])).is_ok());
+        assert!(ServerConfig::from_args(args(&["--door-posn", "100001"])).is_err());
+    }
+}
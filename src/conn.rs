@@ -6,7 +6,7 @@
 //! Handle a player connection, including telnet processing
 //! and setup as well as starting game play.
 
-use crate::GameHandle;
+use crate::{Duplex, Keymap, Transport};
 
 use telnet::{
     Action::*,
@@ -18,7 +18,6 @@ use core::time::*;
 #[cfg(feature = "ansi")]
 use std::collections::HashSet;
 use std::io::{self, ErrorKind, Write};
-use std::net::*;
 
 /// Terminal type information from
 /// https://code.google.com/archive/p/bogboa/wikis/TerminalTypes.wiki
@@ -43,6 +42,55 @@ const SEND: u8 = 1;
 #[cfg(feature = "ansi")]
 const IS: u8 = 0;
 
+/// NEW-ENVIRON subnegotiation commands and codes.
+const ENV_IS: u8 = 0;
+const ENV_SEND: u8 = 1;
+const ENV_VAR: u8 = 0;
+const ENV_VALUE: u8 = 1;
+const ENV_ESC: u8 = 2;
+const ENV_USERVAR: u8 = 3;
+
+/// The `USER` variable from a NEW-ENVIRON `IS`
+/// subnegotiation, if it is there.
+fn environ_user(buf: &[u8]) -> Option<String> {
+    let (&ENV_IS, rest) = buf.split_first()? else {
+        return None;
+    };
+    // Split into variable names and values, undoing
+    // escapes.
+    let mut tokens: Vec<(u8, Vec<u8>)> = Vec::new();
+    let mut bytes = rest.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            ENV_VAR | ENV_VALUE | ENV_USERVAR => tokens.push((b, Vec::new())),
+            ENV_ESC => {
+                if let (Some(&b), Some((_, text))) = (bytes.next(), tokens.last_mut()) {
+                    text.push(b);
+                }
+            }
+            b => {
+                if let Some((_, text)) = tokens.last_mut() {
+                    text.push(b);
+                }
+            }
+        }
+    }
+    tokens
+        .windows(2)
+        .find(|pair| pair[0] == (ENV_VAR, b"USER".to_vec()) && pair[1].0 == ENV_VALUE)
+        .map(|pair| String::from_utf8_lossy(&pair[1].1).into_owned())
+}
+
+/// Something read from the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    /// Typed text.
+    Keys(String),
+    /// The client terminal changed size: see
+    /// [Connection::width] and [Connection::height].
+    Resize,
+}
+
 /// Connection state.
 pub struct Connection {
     /// Telnet client instance.
@@ -55,6 +103,9 @@ pub struct Connection {
     pub cbreak: bool,
     /// Terminal is will echo.
     pub echo: bool,
+    /// Terminal would not do cbreak and noecho, so play
+    /// proceeds a line at a time.
+    pub line_mode: bool,
     /// Terminal is ansi.
     #[cfg(feature = "ansi")]
     pub ansi: bool,
@@ -62,27 +113,39 @@ pub struct Connection {
     pub width: Option<u16>,
     /// Terminal height.
     pub height: Option<u16>,
+    /// Player's name: once they sign in, or the user name the
+    /// client offered.
+    pub name: Option<String>,
+    /// Key bindings chosen by the player.
+    pub keymap: Keymap,
+    /// Last line read ended with a carriage return, so a
+    /// following line feed or NUL should be skipped.
+    skip_lf: bool,
 }
 
 /// Wrap a telnet error as an IO error.
 fn telnet_io_error(te: TelnetError) -> io::Error {
-    io::Error::new(ErrorKind::Other, te)
+    io::Error::other(te)
 }
 
 impl Connection {
     /// Make a new connection state for a stream.
-    pub fn new(stream: TcpStream) -> Connection {
-        let telnet = Telnet::from_stream(Box::new(stream), 256);
+    pub fn new(stream: Box<dyn Duplex>) -> Connection {
+        let telnet = Telnet::from_stream(Box::new(Transport(stream)), 256);
         Connection {
             telnet,
             next_event: None,
             timeout: None,
             cbreak: false,
             echo: true,
+            line_mode: false,
             #[cfg(feature = "ansi")]
             ansi: false,
             width: None,
             height: None,
+            name: None,
+            keymap: Keymap::default(),
+            skip_lf: false,
         }
     }
 
@@ -191,8 +254,12 @@ impl Connection {
                 }
                 Subnegotiation(TTYPE, buf) => {
                     // XXX This code is a mess, and needs miles of love.
-                    assert_eq!(buf[0], IS);
-                    let ttype = String::from_utf8_lossy(&buf[1..]).into_owned();
+                    let Some((&IS, name)) = buf.split_first() else {
+                        eprintln!("telnet: bad TTYPE subnegotiation: {:?}", buf);
+                        self.ansi = false;
+                        return Ok(false);
+                    };
+                    let ttype = String::from_utf8_lossy(name).into_owned();
                     let ttype_lc = ttype.to_lowercase();
 
                     // Check terminal for ANSI-ness.
@@ -249,18 +316,48 @@ impl Connection {
                     self.height = None;
                     return Ok(false);
                 }
-                Subnegotiation(NAWS, buf) => {
-                    assert_eq!(buf.len(), 4);
-                    let width: u16 = ((buf[0] as u16) << 8) | buf[1] as u16;
-                    let height: u16 = ((buf[2] as u16) << 8) | buf[3] as u16;
-                    //eprintln!("terminal winsize {} {}", width, height);
-                    if width > 0 {
-                        self.width = Some(width);
-                    }
-                    if height > 0 {
-                        self.height = Some(height);
-                    }
-                    return Ok(width > 0 || height > 0);
+                Subnegotiation(NAWS, buf) => return Ok(self.set_winsize(&buf)),
+                event => {
+                    // Buffer peek.
+                    self.next_event = Some(event);
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    /// Is the client's terminal known to handle ANSI
+    /// escapes?
+    pub fn is_ansi(&self) -> bool {
+        #[cfg(feature = "ansi")]
+        return self.ansi;
+        #[cfg(not(feature = "ansi"))]
+        false
+    }
+
+    /// Get the client's user name, if it will say, as the
+    /// default player name. This uses the telnet NEW-ENVIRON
+    /// option to ask for the `USER` variable. See [RFC
+    /// 1572](https://www.rfc-editor.org/rfc/rfc1572.html).
+    pub fn negotiate_user(&mut self) -> io::Result<bool> {
+        self.telnet
+            .negotiate(&Do, NewEnvironment)
+            .map_err(telnet_io_error)?;
+        loop {
+            let event = self.get_event()?;
+            use Event::*;
+            match event {
+                Negotiation(Will, NewEnvironment) => {
+                    let mut send = vec![ENV_SEND, ENV_VAR];
+                    send.extend_from_slice(b"USER");
+                    self.telnet
+                        .subnegotiate(NewEnvironment, &send)
+                        .map_err(telnet_io_error)?;
+                }
+                Negotiation(Wont, NewEnvironment) => return Ok(false),
+                Subnegotiation(NewEnvironment, buf) => {
+                    self.name = environ_user(&buf);
+                    return Ok(self.name.is_some());
                 }
                 event => {
                     // Buffer peek.
@@ -271,6 +368,25 @@ impl Connection {
         }
     }
 
+    /// Record the terminal size from a NAWS subnegotiation.
+    /// Returns `false` if it tells nothing.
+    fn set_winsize(&mut self, buf: &[u8]) -> bool {
+        let [w0, w1, h0, h1] = buf[..] else {
+            eprintln!("telnet: bad NAWS subnegotiation: {:?}", buf);
+            return false;
+        };
+        let width = u16::from_be_bytes([w0, w1]);
+        let height = u16::from_be_bytes([h0, h1]);
+        //eprintln!("terminal winsize {} {}", width, height);
+        if width > 0 {
+            self.width = Some(width);
+        }
+        if height > 0 {
+            self.height = Some(height);
+        }
+        width > 0 || height > 0
+    }
+
     /// Set the connection read timeout in milliseconds (if
     /// `Some`) or clear the timeout (if `None).
     pub fn set_timeout(&mut self, ms: Option<u64>) {
@@ -291,14 +407,15 @@ impl Connection {
     }
 
     /// Read data or telnet in-band stuff from the client.
-    /// Honor timeouts.
-    pub fn read(&mut self) -> io::Result<Option<String>> {
+    /// Honor timeouts. Window size changes are returned as
+    /// [Input::Resize].
+    pub fn read(&mut self) -> io::Result<Option<Input>> {
         loop {
             let event = self.get_event()?;
             use Event::*;
             match event {
                 Data(buf) => match String::from_utf8(buf.to_vec()) {
-                    Ok(s) => return Ok(Some(s)),
+                    Ok(s) => return Ok(Some(Input::Keys(s))),
                     Err(e) => {
                         return Err(io::Error::new(ErrorKind::InvalidData, e));
                     }
@@ -308,6 +425,11 @@ impl Connection {
                 Error(err) => {
                     return Err(io::Error::new(ErrorKind::InvalidData, err));
                 }
+                Subnegotiation(NAWS, buf) => {
+                    if self.set_winsize(&buf) {
+                        return Ok(Some(Input::Resize));
+                    }
+                }
                 Subnegotiation(subneg, buf) => {
                     eprintln!("telnet: unexpected subnegotiation: {:?} {:?}", subneg, buf)
                 }
@@ -316,48 +438,106 @@ impl Connection {
         }
     }
 
-    /// Listen for client connections and attach them to the
-    /// game via the given runner.
-    pub fn listen(runner: GameHandle) {
-        let listener = TcpListener::bind("0.0.0.0:10001").unwrap();
+    /// Read a line of text from the client, echoing it as it
+    /// is typed if the client is not echoing. Handles
+    /// backspace. The line terminator is not returned.
+    pub fn read_line(&mut self) -> io::Result<String> {
+        self.read_text(false)
+    }
+
+    /// Read a password from the client, like
+    /// [Connection::read_line] but echoing `*` for each
+    /// character. A client that echoes for itself shows the
+    /// password.
+    pub fn read_password(&mut self) -> io::Result<String> {
+        self.read_text(true)
+    }
+
+    /// Read a line of text, echoing it as typed or `hidden`.
+    fn read_text(&mut self, hidden: bool) -> io::Result<String> {
+        let mut line = String::new();
         loop {
-            match listener.accept() {
-                Ok((mut socket, addr)) => {
-                    println!("new client: {:?}", addr);
-                    let runner = runner.clone();
-                    let _ = std::thread::spawn(move || {
-                        let mut conn = Connection::new(socket.try_clone().unwrap());
-                        match conn.negotiate_winsize() {
-                            Ok(true) => (),
-                            Ok(false) => eprintln!("no winsize"),
-                            Err(e) => eprintln!("no winsize: {}", e),
+            let Some(Input::Keys(data)) = self.read()? else {
+                continue;
+            };
+            for c in data.chars() {
+                let skip_lf = self.skip_lf;
+                self.skip_lf = false;
+                match c {
+                    '\n' | '\0' if skip_lf => (),
+                    '\r' | '\n' => {
+                        self.skip_lf = c == '\r';
+                        if !self.echo {
+                            write!(self, "\r\n")?;
                         }
-                        let termok = conn
-                            .negotiate_cbreak()
-                            .and_then(|_| conn.negotiate_noecho());
-                        match termok {
-                            Ok(true) => (),
-                            e => {
-                                eprintln!("cannot set up terminal: {:?}", e);
-                                socket.write_all(
-    b"Your telnet client cannot be put in no-echo single-character mode\n
-     as needed to play the game. Apologies.\n").unwrap();
-                                socket.flush().unwrap();
-                                return;
-                            }
+                        return Ok(line);
+                    }
+                    '\x08' | '\x7f' => {
+                        let erased = line.pop().is_some();
+                        if erased && !self.echo {
+                            write!(self, "\x08 \x08")?;
                         }
-                        // Don't currently need ANSI.
-                        // assert!(conn.negotiate_ansi().unwrap());
-                        conn.set_timeout(Some(100));
-                        runner.play(conn);
-                    });
-                }
-                Err(e) => {
-                    println!("couldn't get client: {:?}", e);
+                    }
+                    c if !c.is_control() => {
+                        line.push(c);
+                        if !self.echo {
+                            write!(self, "{}", if hidden { '*' } else { c })?;
+                        }
+                    }
+                    _ => (),
                 }
             }
         }
     }
+
+    /// Negotiate the client terminal settings needed for
+    /// play, falling back to line mode if the client will not
+    /// send single characters without echo. Returns the ready
+    /// connection, or `None` if the connection failed.
+    pub fn setup(mut self) -> Option<Connection> {
+        match self.negotiate_winsize() {
+            Ok(true) => (),
+            Ok(false) => eprintln!("no winsize"),
+            Err(e) => eprintln!("no winsize: {}", e),
+        }
+        // Ask for both either way: refusing either means
+        // line mode.
+        let termok = self.negotiate_cbreak().and_then(|cbreak| {
+            let noecho = self.negotiate_noecho()?;
+            Ok(cbreak && noecho)
+        });
+        match termok {
+            Ok(true) => (),
+            Ok(false) => {
+                eprintln!("terminal will not cbreak/noecho: line mode");
+                let _ = self.write_all(
+                    b"Your telnet client cannot be put in no-echo single-character mode,\r\n\
+                      so you are playing in line mode: type commands and press Enter.\r\n\
+                      For example, \"lll.\" moves right three times and then rests.\r\n",
+                );
+                self.line_mode = true;
+            }
+            Err(e) => {
+                eprintln!("cannot set up terminal: {}", e);
+                return None;
+            }
+        }
+        #[cfg(feature = "ansi")]
+        if !self.line_mode {
+            match self.negotiate_ansi() {
+                Ok(true) => (),
+                Ok(false) => eprintln!("no ansi"),
+                Err(e) => eprintln!("no ansi: {}", e),
+            }
+        }
+        match self.negotiate_user() {
+            Ok(true) => (),
+            Ok(false) => eprintln!("no user name"),
+            Err(e) => eprintln!("no user name: {}", e),
+        }
+        self.set_timeout(Some(100));
+        Some(self)
+    }
 }
 
 impl Write for Connection {
@@ -15,13 +15,15 @@ pub enum Object {
     /// Rocks are immovable and inert. A rock blocks the
     /// near end.
     Rock,
-    /// Monsters are by ID.
-    Monster(u64),
+    /// Monsters are by ID, with their display character.
+    Monster(u64, char),
     /// Players are by ID.
     Player(u64),
     /// The door is the exit square, at the
     /// far end.
     Door,
+    /// Items lie on the floor.
+    Item(crate::Item),
 }
 use Object::*;
 
@@ -30,13 +32,36 @@ impl Object {
     pub fn render(&self) -> char {
         match self {
             Rock => '#',
-            Monster(_) => 'M',
+            Monster(_, glyph) => *glyph,
             Player(_) => '@',
             Door => '+',
+            Item(item) => item.render(),
         }
     }
 }
 
+/// What a column of a board view shows, so that a display
+/// can tell things apart by more than their glyphs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tile {
+    /// Playfield not yet created.
+    Blank,
+    /// Empty floor.
+    Floor,
+    /// Rock.
+    Rock,
+    /// The door.
+    Door,
+    /// A hero.
+    Hero,
+    /// A monster other than the boss.
+    Monster,
+    /// The boss.
+    Boss,
+    /// An item on the floor.
+    Item,
+}
+
 /// Things that can be in a location.
 #[derive(Default)]
 pub struct Loc {
@@ -99,7 +124,7 @@ impl Field {
 
     /// Does the position have a monster?
     pub fn has_monster(&self, posn: usize) -> bool {
-        matches!(self[posn].object, Some(Monster(_)))
+        matches!(self[posn].object, Some(Monster(..)))
     }
 
     /// Does the position have a player avatar?
@@ -123,6 +148,57 @@ impl Field {
     pub fn len(&self) -> usize {
         self.0.len()
     }
+
+    /// Saved-game text for the field: its length, then
+    /// `POSN:CODE` for the door and each item on the floor.
+    /// Heroes and MOBs are saved on their own.
+    pub fn snapshot(&self) -> String {
+        let mut text = self.len().to_string();
+        for (posn, loc) in self.0.iter().enumerate() {
+            let code = match loc.floor {
+                Some(Door) => "door",
+                Some(Item(item)) => item.code(),
+                _ => continue,
+            };
+            text += &format!(" {}:{}", posn, code);
+        }
+        text
+    }
+
+    /// Restore a field from its saved-game text, with the
+    /// rock at the near end but no heroes or MOBs.
+    pub fn from_snapshot(text: &str) -> Result<Field, String> {
+        let mut words = text.split_whitespace();
+        let len = words
+            .next()
+            .and_then(|len| len.parse::<usize>().ok())
+            .filter(|&len| len > 0)
+            .ok_or("invalid field length")?;
+        let mut field = Field(vec![Loc {
+            object: Some(Rock),
+            floor: None,
+        }]);
+        field.establish(len - 1);
+        for word in words {
+            let (posn, code) = word
+                .split_once(':')
+                .ok_or_else(|| format!("expected POSN:CODE, got \"{}\"", word))?;
+            let posn = posn
+                .parse::<usize>()
+                .ok()
+                .filter(|&posn| posn < len)
+                .ok_or_else(|| format!("invalid position \"{}\"", posn))?;
+            let floor = match code {
+                "door" => Door,
+                code => Item(
+                    crate::Item::from_code(code)
+                        .ok_or_else(|| format!("unknown item \"{}\"", code))?,
+                ),
+            };
+            field[posn].floor = Some(floor);
+        }
+        Ok(field)
+    }
 }
 
 impl Index<usize> for Field {
@@ -146,7 +222,7 @@ impl Default for Field {
             floor: None,
         }];
         let mut field = Field(field);
-        field.insert_floor(Door, DOOR_POSN);
+        field.insert_floor(Door, config().door_posn);
         field
     }
 }
@@ -13,82 +13,624 @@ use crate::*;
 pub struct Game {
     /// Player ID for *next* client to enter.
     pub next_player_id: u64,
-    /// Player ID map.
-    pub players: HashMap<u64, Player>,
+    /// Player ID map. Ordered, so that iteration order
+    /// (and thus the game) is reproducible from the seed.
+    pub players: BTreeMap<u64, Player>,
     /// Playfield.
     pub field: Field,
     /// Clock: turns passed.
     pub turns: u64,
-    /// MOB ID map.
-    pub monsters: HashMap<u64, Mob>,
+    /// MOB ID map. Ordered, like the player map.
+    pub monsters: BTreeMap<u64, Mob>,
     /// Player ID for *next* MOB to enter.
     pub next_monster_id: u64,
     /// Shared player health.
     pub health: u64,
+    /// The party's one inventory slot, shared by all the
+    /// heroes.
+    pub slot: Option<Item>,
+    /// Weapon the party wields, if any.
+    pub weapon: Option<Item>,
+    /// Armor the party wears, if any.
+    pub armor: Option<Item>,
+    /// MOB ID of the boss guarding the door, while it lives.
+    pub boss: Option<u64>,
+    /// The game has finished and should be reaped.
+    pub over: bool,
+    /// How the game ended, once it has.
+    pub ending: Option<Outcome>,
+    /// Heroes who have escaped.
+    pub escaped: u64,
+    /// Monsters slain.
+    pub kills: u64,
+    /// Damage taken by the party.
+    pub damage_taken: u64,
+    /// Seed the game's random number generator started from.
+    pub seed: u64,
+    /// The game's random number generator. All randomness
+    /// in the game comes from here.
+    pub rng: Rng,
+    /// Recording of the game so far, for replay.
+    pub log: Vec<Record>,
+    /// Heroes resumed from a saved game whose players have
+    /// not come back yet. They stand where they were saved.
+    pub away: BTreeSet<u64>,
+    /// Names of the heroes who have joined, in order, for
+    /// the score.
+    pub heroes: Vec<String>,
+    /// The finished game's recording and score have been
+    /// saved.
+    pub saved: bool,
 }
 
 impl Game {
-    /// Update non-player game state for a new tick.
-    pub fn turn(&mut self) {
+    /// Place a new player avatar with the given name as far
+    /// to the left as feasible, with a view of the given
+    /// terminal width. A player whose hero is away takes it
+    /// back instead. Returns the player's ID, or `None` if
+    /// the game is over.
+    pub fn join(&mut self, name: Option<&str>, width: Option<u16>) -> Option<u64> {
+        if self.over {
+            return None;
+        }
+        if let Some(player_id) = name.and_then(|name| self.away_hero(name)) {
+            self.come_back(player_id, width);
+            return Some(player_id);
+        }
+        let player_id = self.next_player_id;
+        self.next_player_id = player_id + 1;
+        let mut player = Player::new(player_id, name, width);
+        let mut posn = player.posn;
+        while self.field.has_object(posn) {
+            posn += 1;
+        }
+        player.posn = posn;
+        self.heroes.push(player.name.clone());
+        self.players.insert(player_id, player);
+        self.field.insert(Object::Player(player_id), posn);
+        self.field.establish(posn + config().margin);
+        self.record(player_id, Entry::Join(width));
+        self.announce(player_id, &format!("{} joined", self.name(player_id)));
+        Some(player_id)
+    }
+
+    /// Mark every hero away, as when the game is resumed.
+    pub fn send_away(&mut self) {
+        self.away = self.players.keys().copied().collect();
+        self.record(0, Entry::Away);
+    }
+
+    /// Bring the given away hero back into play with a view
+    /// of the given terminal width. Returns `false` if the
+    /// hero is not away.
+    pub fn come_back(&mut self, player_id: u64, width: Option<u16>) -> bool {
+        if !self.away.remove(&player_id) {
+            return false;
+        }
+        self.resize(player_id, width.unwrap_or(Player::WIDTH));
+        self.record(player_id, Entry::Back(width));
+        self.announce(player_id, &format!("{} is back", self.name(player_id)));
+        true
+    }
+
+    /// Name of the given player's hero.
+    pub fn name(&self, player_id: u64) -> String {
+        self.players
+            .get(&player_id)
+            .map_or_else(|| format!("hero {}", player_id), |p| p.name.clone())
+    }
+
+    /// ID of the away hero with the given name, if any.
+    pub fn away_hero(&self, name: &str) -> Option<u64> {
+        self.away
+            .iter()
+            .copied()
+            .find(|id| self.players.get(id).is_some_and(|p| p.name == name))
+    }
+
+    /// The heroes whose players are in the game: all but
+    /// the away heroes.
+    pub fn present(&self) -> impl Iterator<Item = &Player> {
+        self.players.values().filter(|p| !self.away.contains(&p.id))
+    }
+
+    /// Change the given player's view width.
+    pub fn resize(&mut self, player_id: u64, width: u16) {
+        if let Some(player) = self.players.get_mut(&player_id) {
+            player.resize(width);
+        }
+    }
+
+    /// Add an entry to the game recording.
+    fn record(&mut self, player_id: u64, entry: Entry) {
+        self.log.push(Record {
+            turn: self.turns,
+            player_id,
+            entry,
+        });
+    }
+
+    /// Carry out one player action and the rest of the game
+    /// turn that follows it. Returns what happened, ending
+    /// with the game outcome for this player if any. Nothing
+    /// happens if the game is over or the player is not in
+    /// it.
+    pub fn apply(&mut self, player_id: u64, action: Action) -> Vec<GameEvent> {
+        if self.over || !self.players.contains_key(&player_id) || self.away.contains(&player_id) {
+            return Vec::new();
+        }
+        self.record(player_id, Entry::Act(action));
+        let mut events = Vec::new();
+        match action {
+            Action::Move(dir) => events.push(self.step(player_id, dir)),
+            Action::Rest => events.push(GameEvent::Rested(self.rest())),
+            Action::PickUp => events.push(self.pick_up(player_id)),
+            Action::Drop => events.push(self.drop_item(player_id)),
+            Action::Use => events.push(self.use_item(player_id)),
+            Action::Quit => {
+                self.announce(player_id, &format!("{} quit", self.name(player_id)));
+                if self.leave(player_id) {
+                    self.ending = Some(Outcome::Abandoned);
+                    events.push(GameEvent::Abandoned);
+                } else {
+                    events.push(GameEvent::Quit);
+                }
+                return events;
+            }
+        }
+
+        // Run the rest of the game turn.
+        let damage = self.turn();
+        if damage > 0 {
+            events.push(GameEvent::Damaged(damage));
+        }
+        events.extend(self.outcome(player_id));
+        events
+    }
+
+    /// Move the player one step, attacking any monster in the
+    /// way.
+    fn step(&mut self, player_id: u64, dir: Dir) -> GameEvent {
+        let player = self.players.get_mut(&player_id).unwrap();
+        let off = dir.offset();
+        let Some(new_posn) = offset(player.posn, off) else {
+            return GameEvent::Blocked;
+        };
+        match self.field[new_posn].top() {
+            // Combat.
+            Some(&Object::Monster(id, _)) => self.attack(id, new_posn),
+            // Movement blocked.
+            Some(Object::Rock) | Some(Object::Player(_)) => GameEvent::Blocked,
+            // The boss holds the door while it lives.
+            Some(Object::Door) if self.boss.is_some() => GameEvent::Guarded,
+            // Just move, and set up position and view.
+            _ => {
+                player.adjust_display(off);
+                let posn = player.posn;
+                self.field.establish(new_posn + config().margin);
+                player.posn = new_posn;
+                self.field[posn].object = None;
+                self.field[new_posn].object = Some(Object::Player(player_id));
+                GameEvent::Moved(new_posn)
+            }
+        }
+    }
+
+    /// Check whether the game has ended for the given
+    /// player: the party was wiped out, or the player
+    /// escaped. If so the player leaves the game and the
+    /// outcome is returned.
+    pub fn outcome(&mut self, player_id: u64) -> Option<GameEvent> {
+        if self.health == 0 {
+            self.over = true;
+            self.ending = Some(Outcome::Wiped);
+            self.leave(player_id);
+            return Some(GameEvent::Wiped);
+        }
+        let player = self.players.get(&player_id)?;
+        if player.posn >= config().door_posn {
+            // This player avatar escaped the game.
+            self.announce(player_id, &format!("{} escaped", self.name(player_id)));
+            self.escaped += 1;
+            if self.leave(player_id) {
+                // Every player avatar escaped the game.
+                self.ending = Some(Outcome::Won);
+                return Some(GameEvent::Won);
+            }
+            return Some(GameEvent::Escaped);
+        }
+        None
+    }
+
+    /// Render the given player's view of the game: the
+    /// visible part of the field, and the player's column
+    /// within it. Returns `None` if the player is not in the
+    /// game.
+    pub fn view(&self, player_id: u64) -> Option<(String, usize)> {
+        let (left, right) = self.window(player_id)?;
+        // Render player board view.
+        let mut board = self.field.render(left, right);
+
+        // Render player icon.
+        assert_eq!(board.len(), right - left);
+        for (_, p) in self.players.iter() {
+            if p.posn >= left && p.posn < right {
+                board[p.posn - left] = '@';
+            }
+        }
+
+        Some((board.into_iter().collect(), self.players[&player_id].left))
+    }
+
+    /// What each column of the given player's view shows,
+    /// matching [Game::view]. Returns `None` if the player
+    /// is not in the game.
+    pub fn tiles(&self, player_id: u64) -> Option<Vec<Tile>> {
+        let (left, right) = self.window(player_id)?;
+        let tiles = (left..right)
+            .map(|posn| {
+                if posn >= self.field.len() {
+                    return Tile::Blank;
+                }
+                match self.field[posn].top() {
+                    None => Tile::Floor,
+                    Some(Object::Rock) => Tile::Rock,
+                    Some(Object::Door) => Tile::Door,
+                    Some(Object::Player(_)) => Tile::Hero,
+                    Some(&Object::Monster(id, _)) if self.boss == Some(id) => Tile::Boss,
+                    Some(Object::Monster(..)) => Tile::Monster,
+                    Some(Object::Item(_)) => Tile::Item,
+                }
+            })
+            .collect();
+        Some(tiles)
+    }
+
+    /// The span of field positions in the given player's
+    /// view, if they are in the game.
+    fn window(&self, player_id: u64) -> Option<(usize, usize)> {
+        let player = self.players.get(&player_id)?;
+        // Absolute position of left edge in field coords.
+        let left = player.posn - player.left;
+        // Absolute position of right edge in field coords.
+        let right = left + player.width as usize;
+        Some((left, right))
+    }
+
+    /// Post a message to the given player, if they are still
+    /// in the game.
+    pub fn tell(&mut self, player_id: u64, text: String) {
+        if let Some(player) = self.players.get_mut(&player_id) {
+            player.messages.post(text);
+        }
+    }
+
+    /// Post a message to every player in the game except the
+    /// given one.
+    pub fn announce(&mut self, player_id: u64, text: &str) {
+        for (&id, player) in self.players.iter_mut() {
+            if id != player_id {
+                player.messages.post(text.to_string());
+            }
+        }
+    }
+
+    /// Send a chat message from the given player to every
+    /// other player in the game. Only printable ASCII is
+    /// passed on, so that no one can send control sequences
+    /// to other terminals, and the message is cut to
+    /// [MAX_CHAT_LEN] characters. An empty message is not
+    /// sent.
+    pub fn say(&mut self, player_id: u64, text: &str) {
+        let text: String = text
+            .chars()
+            .filter(|&c| c == ' ' || c.is_ascii_graphic())
+            .take(MAX_CHAT_LEN)
+            .collect();
+        let text = text.trim();
+        if !text.is_empty() {
+            self.announce(player_id, &format!("{}: {}", self.name(player_id), text));
+        }
+    }
+
+    /// Player ID of the hero furthest along, if any.
+    pub fn leader(&self) -> Option<u64> {
+        self.players.values().max_by_key(|p| p.posn).map(|p| p.id)
+    }
+
+    /// If the game is over, save its recording and score
+    /// (once).
+    pub fn finish(&mut self) {
+        if !self.over || self.saved {
+            return;
+        }
+        self.saved = true;
+        match save_replay(self.seed, &self.log) {
+            Ok(Some(path)) => println!("saved replay: {}", path.display()),
+            Ok(None) => (),
+            Err(e) => eprintln!("cannot save replay: {}", e),
+        }
+        if let Err(e) = save_score(&self.score()) {
+            eprintln!("cannot save score: {}", e);
+        }
+    }
+
+    /// Update non-player game state for a new tick. Returns
+    /// the damage done to the party.
+    pub fn turn(&mut self) -> u64 {
         // Bump the clock.
         self.turns += 1;
 
         // Spawn MOBs as needed.
         let len = self.field.len();
         let nmonsters = self.monsters.len();
-        if nmonsters < len / 20 && nmonsters < self.turns as usize / 5 {
-            let posn = random(len as u64) as usize;
+        let config = config();
+        if nmonsters < len / config.spawn_tiles
+            && (nmonsters as u64) < self.turns / config.spawn_turns
+        {
+            let posn = random(&mut self.rng, len as u64) as usize;
             if !self.field.has_object(posn) {
                 let id = self.next_monster_id;
                 self.next_monster_id += 1;
-                self.field[posn].object = Some(Object::Monster(id));
-                self.monsters.insert(id, Mob::new(id, posn));
+                let kind = choose_kind(posn, &mut self.rng);
+                let mob = Mob::new(id, posn, kind, &mut self.rng);
+                self.field[posn].object = Some(mob.object());
+                self.monsters.insert(id, mob);
             }
         }
 
+        // Spawn potions and scrolls.
+        self.spawn_items();
+
         // Resolve MOB attacks.
-        for (_, p) in self.players.iter() {
-            for &posn in &[p.posn - 1, p.posn + 1] {
-                if self.field.has_monster(posn) && self.health > 0 {
-                    self.health -= 1;
-                }
-            }
-        }
+        let damage = self.monster_attacks();
+        self.damage_taken += damage;
 
-        // Move MOBs.
-        for m in self.monsters.values_mut() {
+        // Decide MOB moves, then make them in turn.
+        let senses = Senses {
+            field: &self.field,
+            heroes: self.present().map(|p| p.posn).collect(),
+            monsters: &self.monsters,
+        };
+        let moves: Vec<(u64, usize)> = self
+            .monsters
+            .values()
+            .map(|m| (m.id, m.get_move(&senses, &mut self.rng)))
+            .collect();
+        for (id, new_posn) in moves {
+            let m = self.monsters.get_mut(&id).unwrap();
             let posn = m.posn;
-            let new_posn = m.get_move();
             if new_posn == posn {
                 continue;
             }
-            if self.field[new_posn].top().is_some() {
+            // Monsters walk over items, but not through the
+            // door.
+            if self.field.has_object(new_posn)
+                || new_posn >= self.field.len()
+                || self.field[new_posn].floor == Some(Object::Door)
+            {
                 continue;
             }
-            assert_eq!(self.field[posn].top(), Some(&Object::Monster(m.id)));
+            assert_eq!(self.field[posn].top(), Some(&m.object()));
             self.field[posn].object = None;
-            self.field[new_posn].object = Some(Object::Monster(m.id));
+            self.field[new_posn].object = Some(m.object());
             m.posn = new_posn;
         }
+
+        damage
     }
 
-    /// Player rest actions heal the player.
-    pub fn rest(&mut self) {
+    /// Remove a player avatar from the game. Returns `true`
+    /// if that was the last avatar, in which case the game is
+    /// over. While heroes are away the game waits for them.
+    pub fn leave(&mut self, player_id: u64) -> bool {
+        if let Some(player) = self.players.remove(&player_id)
+            && self.field[player.posn].object == Some(Object::Player(player_id))
+        {
+            self.field[player.posn].object = None;
+        }
+        if self.players.is_empty() {
+            self.over = true;
+        }
+        self.over
+    }
+
+    /// Player rest actions heal the player. Returns the
+    /// health regained.
+    pub fn rest(&mut self) -> u64 {
         let health = self.health;
-        self.health = MAX_HEALTH.min(health + random(2));
+        self.health = config().max_health.min(health + random(&mut self.rng, 2));
+        self.health - health
     }
 }
 
-impl Default for Game {
-    fn default() -> Self {
+impl Game {
+    /// Make a new game whose randomness all comes from the
+    /// given seed.
+    pub fn new(seed: u64) -> Self {
+        let mut game = Game::empty(seed);
+        game.place_equipment();
+        game.place_boss();
+        game
+    }
+
+    /// Make a game with the given seed and nothing in it
+    /// yet: no heroes, monsters or items.
+    pub fn empty(seed: u64) -> Self {
         Game {
             next_player_id: 1,
-            players: HashMap::default(),
+            players: BTreeMap::default(),
             field: Field::default(),
             turns: 0,
-            monsters: HashMap::default(),
+            monsters: BTreeMap::default(),
             next_monster_id: 1,
-            health: MAX_HEALTH,
+            health: config().max_health,
+            slot: None,
+            weapon: None,
+            armor: None,
+            boss: None,
+            over: false,
+            ending: None,
+            escaped: 0,
+            kills: 0,
+            damage_taken: 0,
+            seed,
+            rng: Rng::with_seed(seed),
+            log: Vec::new(),
+            away: BTreeSet::new(),
+            heroes: Vec::new(),
+            saved: false,
+        }
+    }
+}
+
+impl Default for Game {
+    /// A new game seeded from the configured seed, if any,
+    /// else at random.
+    fn default() -> Self {
+        Game::new(config().seed.unwrap_or_else(|| fastrand::u64(..)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Play a scripted one-hero game from the given seed
+    /// until the script or the game runs out. Returns the
+    /// game and every event.
+    fn play(seed: u64, actions: &[Action]) -> (Game, Vec<GameEvent>) {
+        let mut game = Game::new(seed);
+        let hero = game.join(Some("tester"), Some(40)).unwrap();
+        let mut events = Vec::new();
+        for &action in actions {
+            if game.over {
+                break;
+            }
+            events.extend(game.apply(hero, action));
+        }
+        (game, events)
+    }
+
+    /// Everything about a game's state that play shows.
+    fn state(game: &Game) -> (u64, u64, String, Vec<(u64, usize, u64)>) {
+        let board = game.field.render(0, game.field.len()).into_iter().collect();
+        let monsters = game
+            .monsters
+            .values()
+            .map(|mob| (mob.id, mob.posn, mob.health()))
+            .collect();
+        (game.turns, game.health, board, monsters)
+    }
+
+    #[test]
+    fn first_moves() {
+        let actions = [
+            Action::Move(Dir::Left),
+            Action::Move(Dir::Right),
+            Action::Rest,
+            Action::Quit,
+        ];
+        let (game, events) = play(7, &actions);
+        assert_eq!(events[0], GameEvent::Blocked);
+        assert_eq!(events[1], GameEvent::Moved(2));
+        assert!(matches!(events[2], GameEvent::Rested(_)));
+        assert_eq!(events[3], GameEvent::Abandoned);
+        assert_eq!(game.turns, 3);
+        assert!(game.over);
+        assert_eq!(game.ending, Some(Outcome::Abandoned));
+    }
+
+    #[test]
+    fn no_play_after_the_end() {
+        let (mut game, _) = play(7, &[Action::Quit]);
+        assert!(game.over);
+        let log = game.log.len();
+        assert_eq!(game.apply(1, Action::Move(Dir::Right)), []);
+        assert_eq!(game.apply(99, Action::PickUp), []);
+        assert_eq!(game.log.len(), log);
+
+        let mut game = Game::new(7);
+        game.join(Some("tester"), Some(40)).unwrap();
+        assert_eq!(game.apply(99, Action::Use), []);
+        assert_eq!(game.turns, 0);
+    }
+
+    #[test]
+    fn finished_games_stay_finished() {
+        let mut game = Game::new(7);
+        let first = game.join(Some("first"), Some(40)).unwrap();
+        let second = game.join(Some("second"), Some(40)).unwrap();
+        game.health = 0;
+        assert_eq!(
+            game.apply(first, Action::Rest).last(),
+            Some(&GameEvent::Wiped)
+        );
+        game.finish();
+        assert!(game.saved);
+        let log = game.log.clone();
+        assert_eq!(game.apply(second, Action::Move(Dir::Right)), []);
+        assert_eq!(game.log, log);
+    }
+
+    #[test]
+    fn poisoned_games_are_over() {
+        let handle = GameHandle::default();
+        let poisoner = handle.clone();
+        let crash = std::thread::spawn(move || {
+            let _state = poisoner.0.lock().unwrap();
+            panic!("crash while playing");
+        });
+        assert!(crash.join().is_err());
+        assert!(handle.is_over());
+        assert_eq!(handle.nplayers(), 0);
+    }
+
+    #[test]
+    fn chat_is_cleaned() {
+        let mut game = Game::new(7);
+        let speaker = game.join(Some("speaker"), Some(40)).unwrap();
+        let listener = game.join(Some("listener"), Some(40)).unwrap();
+        let long = "x".repeat(MAX_CHAT_LEN + 10);
+        game.say(speaker, &format!("hi\x1b[2J there\x07{}", long));
+        game.say(speaker, "\x1b\r\n");
+        let heard: Vec<&str> = game.players[&listener].messages.history().collect();
+        let said = format!("speaker: hi[2J there{}", &long[..MAX_CHAT_LEN - 11]);
+        assert_eq!(heard.last(), Some(&said.as_str()));
+        assert!(!heard.iter().any(|m| m.contains('\x1b')));
+    }
+
+    #[test]
+    fn seeded_games_replay_exactly() {
+        let actions: Vec<Action> = (0..300)
+            .map(|i| match i % 6 {
+                4 => Action::Rest,
+                5 => Action::PickUp,
+                _ => Action::Move(Dir::Right),
+            })
+            .collect();
+        let (a, a_events) = play(1234, &actions);
+        let (b, b_events) = play(1234, &actions);
+        assert_eq!(a_events, b_events);
+        assert_eq!(state(&a), state(&b));
+        assert!(a.turns > 0);
+
+        // The recording alone rebuilds the same game.
+        let mut c = Game::new(a.seed);
+        for record in &a.log {
+            match record.entry {
+                Entry::Join(width) => {
+                    assert_eq!(c.join(None, width), Some(record.player_id));
+                }
+                Entry::Act(action) => {
+                    c.apply(record.player_id, action);
+                }
+                Entry::Away => c.send_away(),
+                Entry::Back(width) => {
+                    assert!(c.come_back(record.player_id, width));
+                }
+            }
         }
+        assert_eq!(state(&a), state(&c));
     }
 }
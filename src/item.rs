@@ -0,0 +1,244 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! Items, and the one inventory slot shared among the
+//! Heroes. Items lie on the floor of the field until a hero
+//! picks one up into the slot. Any hero can then use or drop
+//! whatever the party is holding.
+
+use crate::*;
+
+/// Something that can lie on the floor or be carried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Item {
+    /// A pretty gem. Monsters sometimes leave one behind.
+    Gem,
+    /// The one weapon. Heroes hit harder while the party
+    /// wields it.
+    Sword,
+    /// The one suit of armor. The party takes less damage
+    /// while wearing it.
+    Armor,
+    /// A healing potion. Restores some party health.
+    Potion,
+    /// A scroll of blasting. Destroys every monster near
+    /// the reader.
+    Scroll,
+}
+
+impl Item {
+    /// Every kind of item.
+    pub const ALL: [Item; 5] = [
+        Item::Gem,
+        Item::Sword,
+        Item::Armor,
+        Item::Potion,
+        Item::Scroll,
+    ];
+
+    /// Display character for the item on the floor.
+    pub fn render(&self) -> char {
+        match self {
+            Item::Gem => '*',
+            Item::Sword => ')',
+            Item::Armor => '[',
+            Item::Potion => '!',
+            Item::Scroll => '?',
+        }
+    }
+
+    /// Short name of the item, for saved games.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Item::Gem => "gem",
+            Item::Sword => "sword",
+            Item::Armor => "armor",
+            Item::Potion => "potion",
+            Item::Scroll => "scroll",
+        }
+    }
+
+    /// The item with the given short name, if any.
+    pub fn from_code(code: &str) -> Option<Item> {
+        Item::ALL.into_iter().find(|item| item.code() == code)
+    }
+
+    /// Name of the item, for messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Item::Gem => "a gem",
+            Item::Sword => "a sword",
+            Item::Armor => "a suit of armor",
+            Item::Potion => "a potion",
+            Item::Scroll => "a scroll",
+        }
+    }
+}
+
+/// One in this many slain monsters leaves a gem.
+const GEM_ODDS: u64 = 4;
+
+/// Health restored by a potion.
+const POTION_HEAL: u64 = 25;
+
+/// A scroll blasts monsters up to this many tiles from its
+/// reader.
+const SCROLL_RANGE: usize = 5;
+
+impl Game {
+    /// Put the weapon and armor on the floor somewhere
+    /// between the entrance and the door.
+    pub fn place_equipment(&mut self) {
+        let door_posn = config().door_posn;
+        for item in [Item::Sword, Item::Armor] {
+            let posn = random(&mut self.rng, door_posn as u64 - 1) as usize + 1;
+            if self.field[posn].floor.is_none() {
+                self.field[posn].floor = Some(Object::Item(item));
+            }
+        }
+    }
+
+    /// Pick up the item under the given player into the
+    /// party's slot. If the slot is full, its item is left
+    /// in exchange.
+    pub fn pick_up(&mut self, player_id: u64) -> GameEvent {
+        let posn = self.players[&player_id].posn;
+        let Some(Object::Item(item)) = self.field[posn].floor else {
+            return GameEvent::NoItem;
+        };
+        self.field[posn].floor = self.slot.replace(item).map(Object::Item);
+        self.announce(
+            player_id,
+            &format!("{} picked up {}", self.name(player_id), item.name()),
+        );
+        GameEvent::PickedUp(item)
+    }
+
+    /// Drop the party's item under the given player, if
+    /// there is room on the floor there.
+    pub fn drop_item(&mut self, player_id: u64) -> GameEvent {
+        let Some(item) = self.slot else {
+            return GameEvent::NoItem;
+        };
+        let posn = self.players[&player_id].posn;
+        if self.field[posn].floor.is_some() {
+            return GameEvent::Blocked;
+        }
+        self.slot = None;
+        self.field[posn].floor = Some(Object::Item(item));
+        self.announce(
+            player_id,
+            &format!("{} dropped {}", self.name(player_id), item.name()),
+        );
+        GameEvent::Dropped(item)
+    }
+
+    /// Use the party's item.
+    pub fn use_item(&mut self, player_id: u64) -> GameEvent {
+        let Some(item) = self.slot else {
+            return GameEvent::NoItem;
+        };
+        match item {
+            // Gems are just for looking at.
+            Item::Gem => (),
+            // Equipment goes from the slot onto the party,
+            // and anything it replaces goes into the slot.
+            Item::Sword | Item::Armor => {
+                let worn = match item {
+                    Item::Sword => &mut self.weapon,
+                    _ => &mut self.armor,
+                };
+                self.slot = worn.replace(item);
+                self.announce(
+                    player_id,
+                    &format!(
+                        "{} equipped the party with {}",
+                        self.name(player_id),
+                        item.name()
+                    ),
+                );
+                return GameEvent::Equipped(item);
+            }
+            // Consumables are used up.
+            Item::Potion => {
+                self.slot = None;
+                let health = self.health;
+                self.health = config().max_health.min(health + POTION_HEAL);
+                self.announce(
+                    player_id,
+                    &format!("{} drank a potion", self.name(player_id)),
+                );
+                return GameEvent::Healed(self.health - health);
+            }
+            Item::Scroll => {
+                self.slot = None;
+                let blasted = self.blast(self.players[&player_id].posn);
+                self.announce(
+                    player_id,
+                    &format!("{} read a scroll", self.name(player_id)),
+                );
+                return GameEvent::Blasted(blasted);
+            }
+        }
+        self.announce(
+            player_id,
+            &format!("{} used {}", self.name(player_id), item.name()),
+        );
+        GameEvent::Used(item)
+    }
+
+    /// Tell the given player what the party is holding.
+    pub fn show_slot(&mut self, player_id: u64) {
+        let text = match self.slot {
+            Some(item) => format!("the party holds {}", item.name()),
+            None => "the party holds nothing".to_string(),
+        };
+        self.tell(player_id, text);
+    }
+
+    /// Destroy every monster but the boss within
+    /// [SCROLL_RANGE] tiles of the given position. Returns
+    /// how many there were.
+    fn blast(&mut self, posn: usize) -> usize {
+        let near: Vec<u64> = self
+            .monsters
+            .values()
+            .filter(|mob| !mob.boss && mob.posn.abs_diff(posn) <= SCROLL_RANGE)
+            .map(|mob| mob.id)
+            .collect();
+        for id in &near {
+            let mob = self.monsters.remove(id).unwrap();
+            self.field[mob.posn].object = None;
+        }
+        self.kills += near.len() as u64;
+        near.len()
+    }
+
+    /// Every [ServerConfig::item_turns] turns, put a potion
+    /// or a scroll on some empty floor short of the door.
+    pub fn spawn_items(&mut self) {
+        if !self.turns.is_multiple_of(config().item_turns) {
+            return;
+        }
+        let item = if random(&mut self.rng, 2) == 0 {
+            Item::Potion
+        } else {
+            Item::Scroll
+        };
+        let len = self.field.len().min(config().door_posn);
+        let posn = random(&mut self.rng, len as u64 - 1) as usize + 1;
+        if self.field[posn].floor.is_none() {
+            self.field[posn].floor = Some(Object::Item(item));
+        }
+    }
+
+    /// A monster was slain at the given position: maybe it
+    /// leaves something behind.
+    pub fn leave_loot(&mut self, posn: usize) {
+        if self.field[posn].floor.is_none() && random(&mut self.rng, GEM_ODDS) == 0 {
+            self.field[posn].floor = Some(Object::Item(Item::Gem));
+        }
+    }
+}
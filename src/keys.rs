@@ -0,0 +1,331 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! Key decoding and key bindings. Text from the client is
+//! split into [Key]s by a [KeyBuffer], turning terminal
+//! escape sequences for the arrow and keypad keys into keys
+//! of their own. A player's [Keymap] then says which
+//! [Command] each key gives. The arrow keys work in every
+//! keymap.
+
+use crate::*;
+
+use std::collections::VecDeque;
+
+/// A key pressed on the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// An ordinary character.
+    Char(char),
+    /// Return or Enter.
+    Enter,
+    /// Escape on its own.
+    Escape,
+    /// Left arrow.
+    Left,
+    /// Right arrow.
+    Right,
+    /// Up arrow.
+    Up,
+    /// Down arrow.
+    Down,
+    /// The middle key of the keypad, with Num Lock off.
+    Center,
+}
+
+/// The key sent as the given escape sequence, if known.
+fn escape_key(seq: &str) -> Option<Key> {
+    let key = match seq {
+        // ANSI cursor keys, in normal and application mode.
+        "\x1b[D" | "\x1bOD" => Key::Left,
+        "\x1b[C" | "\x1bOC" => Key::Right,
+        "\x1b[A" | "\x1bOA" => Key::Up,
+        "\x1b[B" | "\x1bOB" => Key::Down,
+        "\x1b[E" | "\x1b[G" | "\x1bOE" => Key::Center,
+        // Keypad digits in application keypad mode.
+        "\x1bOt" => Key::Char('4'),
+        "\x1bOu" => Key::Char('5'),
+        "\x1bOv" => Key::Char('6'),
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Text read from the client, split into keys. A burst of
+/// typing or a paste arrives as one read, and an escape
+/// sequence may be split across reads, so text is buffered
+/// here until it makes whole keys.
+#[derive(Default)]
+pub struct KeyBuffer {
+    /// Text not yet decoded.
+    pending: String,
+    /// The last key was a carriage return, so a line feed or
+    /// NUL right after it is part of the same key.
+    after_cr: bool,
+    /// No more text came for a while, so an unfinished
+    /// escape sequence is not waited for.
+    idle: bool,
+}
+
+impl KeyBuffer {
+    /// Add text read from the client.
+    pub fn push(&mut self, data: &str) {
+        self.pending.push_str(data);
+        self.idle = false;
+    }
+
+    /// Note that a read from the client timed out. A lone
+    /// escape is then taken to be the Escape key.
+    pub fn timed_out(&mut self) {
+        self.idle = true;
+    }
+
+    /// Take the next whole key, if any. Unknown escape
+    /// sequences are skipped.
+    pub fn next_key(&mut self) -> Option<Key> {
+        loop {
+            let c = self.pending.chars().next()?;
+            let after_cr = std::mem::take(&mut self.after_cr);
+            if c == '\x1b' {
+                // Length of the escape sequence, if it is all
+                // here: `[` or `O`, parameters, then a final
+                // character.
+                let len = match self.pending[1..].chars().next() {
+                    Some('[' | 'O') => self
+                        .pending
+                        .char_indices()
+                        .skip(2)
+                        .find(|&(_, c)| !(c.is_ascii_digit() || c == ';'))
+                        .map(|(i, c)| i + c.len_utf8()),
+                    Some(_) => Some(1),
+                    None => None,
+                };
+                match len {
+                    None if !self.idle => {
+                        // Wait for the rest.
+                        self.after_cr = after_cr;
+                        return None;
+                    }
+                    // Escape on its own.
+                    None | Some(1) => {
+                        self.pending.remove(0);
+                        return Some(Key::Escape);
+                    }
+                    Some(len) => {
+                        let seq: String = self.pending.drain(..len).collect();
+                        match escape_key(&seq) {
+                            Some(key) => return Some(key),
+                            None => continue,
+                        }
+                    }
+                }
+            }
+            self.pending.remove(0);
+            match c {
+                '\n' | '\0' if after_cr => (),
+                '\r' => {
+                    self.after_cr = true;
+                    return Some(Key::Enter);
+                }
+                '\n' => return Some(Key::Enter),
+                c => return Some(Key::Char(c)),
+            }
+        }
+    }
+}
+
+/// Actions typed ahead, waiting their turn. At most
+/// [ServerConfig::max_queued] ordinary actions wait; more are
+/// dropped. Quitting is never dropped: it clears the queue
+/// and is taken next.
+#[derive(Default)]
+pub struct ActionQueue(VecDeque<Action>);
+
+impl ActionQueue {
+    /// Add an action to the queue, if there is room.
+    pub fn push(&mut self, action: Action) {
+        if action == Action::Quit {
+            self.0.clear();
+            self.0.push_back(action);
+        } else if self.0.len() < config().max_queued {
+            self.0.push_back(action);
+        }
+    }
+
+    /// Take the next action, if any.
+    pub fn pop(&mut self) -> Option<Action> {
+        self.0.pop_front()
+    }
+}
+
+/// What a player asks for with a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Take a game action.
+    Act(Action),
+    /// Show what the party is holding.
+    Inventory,
+    /// Show recent messages.
+    Messages,
+    /// Start a chat message.
+    Chat,
+    /// Take down the message being shown.
+    Dismiss,
+    /// Take down all waiting messages.
+    DismissAll,
+    /// Switch to the next keymap.
+    Keymap,
+}
+
+/// A set of key bindings.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Keymap {
+    /// `h` and `l` to move, as in vi.
+    #[default]
+    Vi,
+    /// Keypad digits to move, for arrow key fans.
+    Arrows,
+    /// `a` and `d` to move, as in many PC games.
+    Wasd,
+}
+
+impl Keymap {
+    /// Every keymap, in the order they are switched through.
+    pub const ALL: [Keymap; 3] = [Keymap::Vi, Keymap::Arrows, Keymap::Wasd];
+
+    /// Name of the keymap, as the player chooses it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Keymap::Vi => "vi",
+            Keymap::Arrows => "arrows",
+            Keymap::Wasd => "wasd",
+        }
+    }
+
+    /// The keymap with the given name, if any.
+    pub fn from_name(name: &str) -> Option<Keymap> {
+        Keymap::ALL.into_iter().find(|keymap| keymap.name() == name)
+    }
+
+    /// The keymap after this one.
+    pub fn next(self) -> Keymap {
+        let i = Keymap::ALL.iter().position(|&k| k == self).unwrap();
+        Keymap::ALL[(i + 1) % Keymap::ALL.len()]
+    }
+
+    /// Short description of the keys that differ between
+    /// keymaps.
+    pub fn help(self) -> &'static str {
+        match self {
+            Keymap::Vi => "h/l move, . rest, g get, d drop, u use",
+            Keymap::Arrows => "arrows or 4/6 move, 5 or . rest, g get, d drop, u use",
+            Keymap::Wasd => "a/d move, s rest, g get, x drop, u use",
+        }
+    }
+
+    /// The command given by a key, if any.
+    pub fn command(self, key: Key) -> Option<Command> {
+        use Keymap::*;
+        let action = match (self, key) {
+            // Keys common to every keymap.
+            (_, Key::Left) => Action::Move(Dir::Left),
+            (_, Key::Right) => Action::Move(Dir::Right),
+            (_, Key::Center) => Action::Rest,
+            (_, Key::Char('q')) => Action::Quit,
+            (_, Key::Char('g')) => Action::PickUp,
+            (_, Key::Char('u')) => Action::Use,
+            (_, Key::Char('i')) => return Some(Command::Inventory),
+            (_, Key::Char('m')) => return Some(Command::Messages),
+            (_, Key::Char('t')) => return Some(Command::Chat),
+            (_, Key::Char('k')) => return Some(Command::Keymap),
+            (_, Key::Char(' ')) => return Some(Command::Dismiss),
+            (_, Key::Enter) => return Some(Command::DismissAll),
+
+            (Vi, Key::Char('h')) => Action::Move(Dir::Left),
+            (Vi, Key::Char('l')) => Action::Move(Dir::Right),
+            (Vi | Arrows, Key::Char('.')) => Action::Rest,
+            (Vi | Arrows, Key::Char('d')) => Action::Drop,
+
+            (Arrows, Key::Char('4')) => Action::Move(Dir::Left),
+            (Arrows, Key::Char('6')) => Action::Move(Dir::Right),
+            (Arrows, Key::Char('5')) => Action::Rest,
+
+            (Wasd, Key::Char('a')) => Action::Move(Dir::Left),
+            (Wasd, Key::Char('d')) => Action::Move(Dir::Right),
+            (Wasd, Key::Char('s')) => Action::Rest,
+            (Wasd, Key::Char('x')) => Action::Drop,
+
+            _ => return None,
+        };
+        Some(Command::Act(action))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every whole key in the buffer so far.
+    fn drain(keys: &mut KeyBuffer) -> Vec<Key> {
+        std::iter::from_fn(|| keys.next_key()).collect()
+    }
+
+    #[test]
+    fn split_escape_sequences_wait() {
+        let mut keys = KeyBuffer::default();
+        keys.push("l\x1b[");
+        assert_eq!(drain(&mut keys), [Key::Char('l')]);
+        keys.push("Dh");
+        assert_eq!(drain(&mut keys), [Key::Left, Key::Char('h')]);
+    }
+
+    #[test]
+    fn lone_escape_waits_for_a_timeout() {
+        let mut keys = KeyBuffer::default();
+        keys.push("\x1b");
+        assert_eq!(drain(&mut keys), []);
+        keys.timed_out();
+        assert_eq!(drain(&mut keys), [Key::Escape]);
+    }
+
+    #[test]
+    fn line_ends_are_one_enter() {
+        let mut keys = KeyBuffer::default();
+        keys.push("a\r\nb\r\0c\nd\r");
+        keys.push("\n");
+        let want = [
+            Key::Char('a'),
+            Key::Enter,
+            Key::Char('b'),
+            Key::Enter,
+            Key::Char('c'),
+            Key::Enter,
+            Key::Char('d'),
+            Key::Enter,
+        ];
+        assert_eq!(drain(&mut keys), want);
+    }
+
+    #[test]
+    fn unknown_escape_sequences_are_skipped() {
+        let mut keys = KeyBuffer::default();
+        keys.push("\x1b[5~l\x1b[1;5Ch\x1bOP.");
+        let want = [Key::Char('l'), Key::Char('h'), Key::Char('.')];
+        assert_eq!(drain(&mut keys), want);
+    }
+
+    #[test]
+    fn quit_is_never_dropped() {
+        let mut queue = ActionQueue::default();
+        for _ in 0..config().max_queued + 2 {
+            queue.push(Action::Rest);
+        }
+        queue.push(Action::Quit);
+        queue.push(Action::Rest);
+        assert_eq!(queue.pop(), Some(Action::Quit));
+        assert_eq!(queue.pop(), Some(Action::Rest));
+        assert_eq!(queue.pop(), None);
+    }
+}
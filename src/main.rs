@@ -10,9 +10,17 @@
 *[GameHandle] implements the play loop in its [play()]
 *function.
 
-At startup, a new [GameHandle] is created. Then the
+The game rules themselves are headless: a client drives the
+game only through [Game::apply], which takes a typed
+[Action] and returns the [GameEvent]s it caused. The play
+loop just maps keystrokes to actions and events to text.
+
+At startup, an empty [GameRegistry] is created. Then the
 connection listener is started. As players connect, they are
-placed into the game and given a dedicated client proxy.
+given a dedicated client proxy and placed in a text lobby,
+where they can create a new game or join a running one.
+Each game is held by its own [GameHandle]; games that have
+finished are reaped from the registry.
 
 OWO is a "turn-based" game, with the provision that the
 world updates when *any* player acts. This "time only moves
@@ -21,41 +29,85 @@ turn, the game simulation steps forward a step, regardless
 of the amount of elapsed time since the last turn.
 */
 
+mod accounts;
+mod action;
+mod ai;
+#[cfg(feature = "ansi")]
+mod ansi;
+mod boss;
+mod combat;
+mod config;
 mod conn;
 mod field;
 mod game;
+mod item;
+mod keys;
+mod message;
 mod mob;
+mod monster;
 mod player;
+mod registry;
+mod replay;
+mod scores;
+mod snapshot;
+mod spectate;
+mod status;
+mod textfile;
+mod transport;
+mod web;
 
+pub use accounts::*;
+pub use action::*;
+pub use ai::*;
+#[cfg(feature = "ansi")]
+pub use ansi::*;
+pub use config::*;
 pub use conn::*;
 pub use field::*;
 pub use game::*;
+pub use item::*;
+pub use keys::*;
+pub use message::*;
 pub use mob::*;
+pub use monster::*;
 pub use player::*;
+pub use registry::*;
+pub use replay::*;
+pub use scores::*;
+pub use snapshot::*;
+pub use textfile::*;
+pub use transport::*;
+pub use web::*;
 
-use std::borrow::BorrowMut;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 pub use std::io::{self, Write};
+use std::sync::MutexGuard;
 pub use std::sync::{Arc, Mutex};
 
-use fastrand::u64 as random_u64;
+pub use fastrand::Rng;
 
-pub fn random(r: u64) -> u64 {
-    random_u64(0..r)
+/// Random number in `0..r` from the given generator. All
+/// game randomness goes through a per-game generator so that
+/// a game can be reproduced from its seed.
+pub fn random(rng: &mut Rng, r: u64) -> u64 {
+    rng.u64(0..r)
 }
 
 /// The "health" and other player characteristics are common
 /// across all players in the instance; the individual
 /// players are proxy avatars that have only their own
-/// position and actions. This is the maximum health that
-/// the player can have.
+/// position and actions. This is the default maximum health
+/// that the player can have.
 pub const MAX_HEALTH: u64 = 100;
 
-
 /// The game is won by traversing this distance (in tiles)
-/// to the exit door.
+/// to the exit door. This is the default; see
+/// [ServerConfig::door_posn].
 pub const DOOR_POSN: usize = 500;
 
+/// Longest chat message in characters.
+pub const MAX_CHAT_LEN: usize = 200;
+
 /// This contains all of the game state during a game.  Its
 /// refcount will go to zero only when the game is
 /// over. Individual client proxies must lock it to act.
@@ -63,181 +115,440 @@ pub const DOOR_POSN: usize = 500;
 pub struct GameHandle(Arc<Mutex<Game>>);
 
 impl GameHandle {
+    /// Take the state lock. A game whose lock was poisoned
+    /// by a panic is over, so that it is reaped rather than
+    /// taking down everything that looks at it.
+    fn lock(&self) -> MutexGuard<'_, Game> {
+        self.0.lock().unwrap_or_else(|poisoned| {
+            let mut state = poisoned.into_inner();
+            state.over = true;
+            state
+        })
+    }
+
     /// Execute some game action code under the state lock.
     fn with_game<T>(&mut self, mut action: impl FnMut(&mut Game) -> T) -> T {
-        let mut state = self.0.borrow_mut().lock().unwrap();
-        action(&mut state)
+        action(&mut self.lock())
+    }
+
+    /// Has this game finished?
+    pub fn is_over(&self) -> bool {
+        self.lock().over
+    }
+
+    /// Random seed the game started from.
+    pub fn seed(&self) -> u64 {
+        self.lock().seed
+    }
+
+    /// Number of player avatars currently in the game.
+    pub fn nplayers(&self) -> usize {
+        self.lock().players.len()
     }
 
     /// The main play loop for a single player client avatar.
+    /// Returns `false` if the remote connection was lost.
     ///
     /// Note that the "player" is an avatar of the single
     /// notional entity here. A player has a unique position
     /// and can take unique actions.  A player is associated
     /// with a unique remote connection.
-    pub fn play(mut self, mut remote: Connection) {
+    pub fn play(mut self, remote: &mut Connection) -> bool {
         // Start the player as far to the left as feasible,
-        // then set up their view.
-        let player_id = self.with_game(|game| {
-            let player_id = game.next_player_id;
-            game.next_player_id = player_id + 1;
-            let mut player = Player::new(player_id, remote.width);
-            let mut posn = player.posn;
-            while game.field.has_object(posn) {
-                posn += 1;
-            }
-            player.posn = posn;
-            game.players.insert(player_id, player);
-            game.field[posn].object = Some(Object::Player(player_id));
-            game.field.establish(posn + Player::MARGIN);
-            player_id
-        });
+        // then set up their view. The board view gives up
+        // room for the status display if need be.
+        let (area, width) = StatusArea::layout(remote);
+        let name = remote.name.clone();
+        let Some(player_id) = self.with_game(|game| game.join(name.as_deref(), Some(width))) else {
+            let _ = write!(remote, "\rthat game is over\r\n");
+            return true;
+        };
+
+        let mut tally = Tally::default();
+        let live = self.run(remote, player_id, area, &mut tally);
+        self.with_game(|game| game.finish());
+        if let Some(name) = &remote.name
+            && let Err(e) = record_game(name, tally)
+        {
+            eprintln!("cannot save account {}: {}", name, e);
+        }
+        live
+    }
 
-        // Read and execute player actions.
+    /// Run the play loop for the given player, whose status
+    /// display goes in `area`, keeping the player's `tally`.
+    /// Returns `false` if the remote connection was lost.
+    fn run(
+        &mut self,
+        remote: &mut Connection,
+        player_id: u64,
+        area: StatusArea,
+        tally: &mut Tally,
+    ) -> bool {
+        // Read and execute player actions. Actions typed
+        // ahead wait their turn in a queue, and are taken
+        // one per pass.
+        let mut screen = screen_for(remote, area);
+        let mut line_buf = String::new();
+        let mut keys = KeyBuffer::default();
+        let mut queued = ActionQueue::default();
+        // Chat line being composed, if any.
+        let mut chat: Option<String> = None;
         loop {
             let optcmd = match remote.read() {
-                Ok(cmd) => cmd,
+                Ok(Some(Input::Keys(cmd))) => Some(cmd),
+                Ok(Some(Input::Resize)) => {
+                    // Lay out and redraw the display anew.
+                    let (area, width) = StatusArea::layout(remote);
+                    self.with_game(|game| game.resize(player_id, width));
+                    screen = screen_for(remote, area);
+                    None
+                }
+                Ok(None) => {
+                    keys.timed_out();
+                    None
+                }
                 Err(e) => {
                     eprintln!("net read error: {}", e);
-                    return;
+                    self.with_game(|game| game.apply(player_id, Action::Quit));
+                    return false;
                 }
             };
-            if let Some(cmd) = optcmd {
-                let cmd = cmd.trim();
-                match cmd {
-                    "h" | "l" => self.with_game(|game| {
-                        // Movement command.
-                        let player = game.players.get_mut(&player_id).unwrap();
-                        let off = match cmd {
-                            "h" => -1,
-                            "l" => 1,
-                            _ => panic!("internal error: bad cmd"),
-                        };
 
-                        //  Act on command.
-                        if let Some(new_posn) = offset(player.posn, off) {
-                            let clear = match game.field[new_posn].top() {
-                                Some(Object::Monster(id)) => {
-                                    // Combat.
-                                    let mob = game.monsters.get_mut(id).unwrap();
-                                    if !mob.hit() {
-                                        // Killed the monster.
-                                        game.monsters.remove(id);
-                                        game.field[new_posn].object = None;
-                                        true
-                                    } else {
-                                        false
-                                    }
-                                }
-                                // Movement blocked.
-                                Some(_) => false,
-                                // Just move.
-                                _ => true,
-                            };
-
-                            // If successfully moved, set up position and view.
-                            if clear {
-                                player.adjust_display(off);
-                                let posn = player.posn;
-                                game.field.establish(new_posn + Player::MARGIN);
-                                player.posn = new_posn;
-                                game.field[posn].object = None;
-                                game.field[new_posn].object = Some(Object::Player(player.id));
-                            }
+            // Gather the keys typed. In line mode each
+            // complete line is a sequence of one-character
+            // commands, or `t` and a chat message.
+            match optcmd {
+                None => (),
+                Some(data) if remote.line_mode => {
+                    line_buf.push_str(&data);
+                    while let Some(end) = line_buf.find(['\r', '\n']) {
+                        let line: String = line_buf.drain(..=end).collect();
+                        let line = line.trim();
+                        if let Some(text) = line.strip_prefix("t ") {
+                            self.with_game(|game| game.say(player_id, text));
+                            continue;
                         }
-                    }),
-                    // Rest.
-                    "." => self.with_game(|game| game.rest()),
-                    // Quit the game.
-                    "q" => {
-                        self.with_game(|game| {
-                            let player = &game.players[&player_id];
-                            game.field[player.posn].object = None;
-                            game.players.remove(&player_id).unwrap();
-                            if game.players.is_empty() {
-                                writeln!(remote, "\rno more players, new game    \r").unwrap();
-                                *game = Game::default();
-                                return;
-                            }
-                            writeln!(remote, "\ryou quit, how sad    \r").unwrap();
-                        });
-                        return;
+                        keys.push(&line.replace(char::is_whitespace, ""));
                     }
-                    // Ignore random commands.
-                    _ => continue,
                 }
-
-                // Run the rest of the game turn.
-                self.with_game(|game| game.turn());
+                Some(data) => keys.push(&data),
             }
 
-            // Check for player dead or game win.
-            let done = self.with_game(|game| {
-                if game.health == 0 {
-                    // Only one player, and they died.
-                    writeln!(remote, "\rboard wipe, game over    \r").unwrap();
-                    game.players.remove(&player_id).unwrap();
-                    if game.players.is_empty() {
-                        *game = Game::default();
+            // Act on the keys. Keys typed while composing a
+            // chat message go into the message, actions go in
+            // the queue, and other commands are done at once.
+            while let Some(key) = keys.next_key() {
+                if let Some(text) = chat.as_mut() {
+                    match key {
+                        Key::Enter => {
+                            let text = chat.take().unwrap();
+                            self.with_game(|game| game.say(player_id, &text));
+                        }
+                        // Escape cancels the message.
+                        Key::Escape => chat = None,
+                        Key::Char('\x08' | '\x7f') => {
+                            text.pop();
+                        }
+                        Key::Char(c)
+                            if (c == ' ' || c.is_ascii_graphic()) && text.len() < MAX_CHAT_LEN =>
+                        {
+                            text.push(c);
+                        }
+                        _ => (),
                     }
-                    return true;
+                    continue;
                 }
-                let player = game.players.get(&player_id).unwrap();
-                if player.posn >= DOOR_POSN {
-                    // This player avatar escaped the game.
-                    game.players.remove(&player_id).unwrap();
-                    if game.players.is_empty() {
-                        // Every player avatar escaped the game.
-                        writeln!(remote, "\ry'all escaped, win!    \r").unwrap();
-                        *game = Game::default();
-                        return true;
+                let Some(cmd) = remote.keymap.command(key) else {
+                    // Ignore random keys.
+                    continue;
+                };
+                match cmd {
+                    Command::Act(action) => queued.push(action),
+                    // Line mode chats with `t` lines.
+                    Command::Chat if remote.line_mode => (),
+                    Command::Chat => chat = Some(String::new()),
+                    // Space takes down the message overlay,
+                    // Enter takes down all waiting messages.
+                    Command::Dismiss => {
+                        self.with_game(|game| {
+                            game.players
+                                .get_mut(&player_id)
+                                .map(|p| p.messages.dismiss())
+                        });
+                    }
+                    Command::DismissAll => {
+                        self.with_game(|game| {
+                            game.players
+                                .get_mut(&player_id)
+                                .map(|p| p.messages.dismiss_all())
+                        });
+                    }
+                    Command::Inventory => {
+                        self.with_game(|game| game.show_slot(player_id));
+                    }
+                    Command::Messages => {
+                        if self.scrollback(remote, screen.as_mut(), player_id) {
+                            return true;
+                        }
+                    }
+                    Command::Keymap => {
+                        remote.keymap = remote.keymap.next();
+                        let text =
+                            format!("keys {}: {}", remote.keymap.name(), remote.keymap.help());
+                        self.with_game(|game| game.tell(player_id, text.clone()));
                     }
-                    writeln!(remote, "\ryou escaped, one down    \r").unwrap();
-                    return true;
                 }
+            }
+
+            // Take the next queued action, if any, and show
+            // the result.
+            let events = queued
+                .pop()
+                .map(|action| self.with_game(|game| game.apply(player_id, action)));
+            if let Some(events) = &events {
+                tally.count(events);
+            }
+            if self.update(
+                remote,
+                screen.as_mut(),
+                player_id,
+                events.as_deref(),
+                chat.as_deref(),
+            ) {
+                return true;
+            }
+        }
+    }
+
+    /// Show the player their recent messages on lines of
+    /// their own, then redraw their view. Returns `true` if
+    /// the player is done, as for [GameHandle::update].
+    fn scrollback(
+        &mut self,
+        remote: &mut Connection,
+        screen: &mut dyn Render,
+        player_id: u64,
+    ) -> bool {
+        let history: Vec<String> = self.with_game(|game| {
+            game.players
+                .get(&player_id)
+                .map(|player| player.messages.history().map(String::from).collect())
+                .unwrap_or_default()
+        });
+        if history.is_empty() {
+            screen.print(remote, "no messages");
+        }
+        for message in history {
+            screen.print(remote, &message);
+        }
+        self.update(remote, screen, player_id, None, None)
+    }
 
-                // Render player scene.
-                //
-                // Absolute position of player in field coords.
-                let posn = player.posn;
-                // Absolute position of left edge in field coords.
-                let left = posn - player.left;
-                // Width of display in characters.
-                let width = player.width as usize;
-                // Absolute position of right edge in field coords.
-                let right = left + width;
-                // Render player board view.
-                let mut board = game.field.render(left, right);
-
-                // Render player icon.
-                assert_eq!(board.len(), width);
-                for (_, p) in game.players.iter() {
-                    if p.posn >= left && p.posn < right {
-                        board[p.posn - left] = '@';
+    /// Report the player's game events, then check for the
+    /// game ending for them. If the game goes on, send the
+    /// player their view with any message overlaid on it
+    /// (or, if they are `composing` a chat message, their
+    /// message so far). `events` is `None` if the player did
+    /// not act. Returns `true` if the player is done.
+    fn update(
+        &mut self,
+        remote: &mut Connection,
+        screen: &mut dyn Render,
+        player_id: u64,
+        events: Option<&[GameEvent]>,
+        composing: Option<&str>,
+    ) -> bool {
+        let acted = events.is_some();
+        self.with_game(|game| {
+            // Events from the player's own action, or the
+            // game outcome if someone else's action ended it.
+            // An action comes to nothing once the game is over.
+            let outcome;
+            let events = match events {
+                Some(events) if !events.is_empty() => events,
+                _ => {
+                    outcome = game.outcome(player_id);
+                    outcome.as_slice()
+                }
+            };
+            for event in events {
+                let message = match event {
+                    GameEvent::Killed(_) => {
+                        game.tell(player_id, "you slew a monster".to_string());
+                        continue;
+                    }
+                    GameEvent::PickedUp(item)
+                    | GameEvent::Dropped(item)
+                    | GameEvent::Used(item) => {
+                        let verb = match event {
+                            GameEvent::PickedUp(_) => "picked up",
+                            GameEvent::Dropped(_) => "dropped",
+                            _ => "used",
+                        };
+                        game.tell(player_id, format!("you {} {}", verb, item.name()));
+                        continue;
+                    }
+                    GameEvent::BossKilled => {
+                        game.announce(
+                            player_id,
+                            &format!("{} slew the boss", game.name(player_id)),
+                        );
+                        game.tell(player_id, "you slew the boss: the door is open".to_string());
+                        continue;
+                    }
+                    GameEvent::Guarded => {
+                        game.tell(player_id, "the boss bars the door".to_string());
+                        continue;
+                    }
+                    GameEvent::Equipped(item) => {
+                        game.tell(player_id, format!("the party now has {}", item.name()));
+                        continue;
+                    }
+                    GameEvent::Healed(health) => {
+                        game.tell(player_id, format!("the potion heals {}", health));
+                        continue;
+                    }
+                    GameEvent::Blasted(count) => {
+                        game.tell(player_id, format!("the scroll blasts {} monsters", count));
+                        continue;
                     }
+                    GameEvent::NoItem => {
+                        game.tell(player_id, "nothing there".to_string());
+                        continue;
+                    }
+                    GameEvent::Quit => "you quit, how sad",
+                    GameEvent::Abandoned => "no more players, game over",
+                    GameEvent::Wiped => "board wipe, game over",
+                    GameEvent::Escaped => "you escaped, one down",
+                    GameEvent::Won => "y'all escaped, win!",
+                    _ => continue,
+                };
+                screen.finish(remote, message);
+                return true;
+            }
+
+            // The status, taken before the player's messages
+            // are borrowed for the view.
+            let compact = matches!(screen.area(), StatusArea::Segment(_));
+            let Some(status) = game.status(player_id, compact) else {
+                screen.finish(remote, "the game is over");
+                return true;
+            };
+
+            // Clients without cursor control get messages on
+            // lines of their own.
+            let player = game.players.get_mut(&player_id).unwrap();
+            if remote.line_mode {
+                for message in player.messages.drain() {
+                    screen.print(remote, &message);
                 }
+            }
 
-                // Set up the render and send it.
-                let render: String = board.into_iter().collect();
-                let posn = player.posn;
-                if posn != player.posn_cache || render != player.display_cache {
-                    write!(remote, "\r{}", render).unwrap();
-                    write!(remote, "\r{}", &render[0..posn - left]).unwrap();
+            // Render the chat message being composed, or the
+            // view with any message.
+            let width = player.width as usize;
+            let (render, cursor) = match composing {
+                Some(text) => {
+                    let prompt = format!("say: {}", text);
+                    let cursor = prompt.len().min(width);
+                    let start = prompt.len() - cursor;
+                    let prompt = format!("{:width$}", &prompt[start..]);
+                    (Showing::Text(prompt), cursor)
+                }
+                None => {
+                    let (render, cursor) = game.view(player_id).unwrap();
+                    let tiles = game.tiles(player_id).unwrap();
                     let player = game.players.get_mut(&player_id).unwrap();
-                    player.display_cache = render;
-                    player.posn_cache = posn;
+                    let message = player.messages.current();
+                    (Showing::Board(render, tiles, message), cursor)
                 }
-                false
-            });
-
-            // Player and maybe game over.
-            if done {
-                return;
-            }
-        }
+            };
+            screen.draw(remote, render, &status, cursor, acted);
+            false
+        })
     }
 }
 
 fn main() {
-    Connection::listen(GameHandle::default());
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "replay") {
+        if let Err(e) = replay_main(&args[1..]) {
+            eprintln!("one-way-out: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.iter().any(|arg| arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+    let show_scores = args.iter().any(|arg| arg == "--scores");
+    let config = match ServerConfig::from_args(args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("one-way-out: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if show_scores {
+        match load_scores(&config.score_file) {
+            Ok(scores) => {
+                for line in leaderboards(&scores) {
+                    println!("{}", line);
+                }
+            }
+            Err(e) => {
+                eprintln!("one-way-out: scores: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Err(e) = install_kinds(config.monsters.as_deref()) {
+        eprintln!("one-way-out: monsters: {}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = install_accounts(&config.account_file) {
+        eprintln!("one-way-out: accounts: {}", e);
+        std::process::exit(1);
+    }
+    let unix = config.unix.clone();
+    let web_port = config.web_port;
+    let save_file = config.save_file.clone();
+    config.install().unwrap();
+    let registry = GameRegistry::default();
+    if let Some(path) = save_file {
+        match load_games(&path) {
+            Ok(games) => registry.resume(games),
+            Err(e) => {
+                eprintln!("one-way-out: saved games: {}", e);
+                std::process::exit(1);
+            }
+        }
+        let registry = registry.clone();
+        let _ = std::thread::spawn(move || autosave(registry, &path));
+    }
+    if let Some(path) = unix {
+        let registry = registry.clone();
+        let _ = std::thread::spawn(move || {
+            if let Err(e) = listen_unix(registry, &path) {
+                eprintln!("one-way-out: {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        });
+    }
+    if let Some(port) = web_port {
+        let registry = registry.clone();
+        let _ = std::thread::spawn(move || {
+            if let Err(e) = listen_web(registry, port) {
+                eprintln!("one-way-out: web port {}: {}", port, e);
+                std::process::exit(1);
+            }
+        });
+    }
+    if let Err(e) = listen_tcp(registry) {
+        eprintln!("one-way-out: {}", e);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,356 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! Messages to a player, and the player's display. Each
+//! player has a queue of [Messages]; the one at the front is
+//! shown for a while as an overlay on part of the board
+//! line, away from the hero, or on a line of its own on an
+//! ANSI terminal. A [Render] keeps track of what
+//! the client is currently showing, so that only changes are
+//! sent. The status display goes on a line of its own if the
+//! client's terminal has room, else in a segment of the
+//! board line: see [StatusArea].
+
+use crate::*;
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How long a message overlay stays up unless dismissed.
+const OVERLAY_TIME: Duration = Duration::from_secs(3);
+
+/// How many recent messages are kept for scrollback.
+const HISTORY_LEN: usize = 10;
+
+/// A player's message queue.
+#[derive(Default)]
+pub struct Messages {
+    /// Messages not yet shown.
+    pending: VecDeque<String>,
+    /// Message being shown, and when it went up.
+    showing: Option<(String, Instant)>,
+    /// Most recent messages, oldest first.
+    history: VecDeque<String>,
+}
+
+impl Messages {
+    /// Queue a message for display.
+    pub fn post(&mut self, text: String) {
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(text.clone());
+        self.pending.push_back(text);
+    }
+
+    /// Take down the message being shown, if any.
+    pub fn dismiss(&mut self) {
+        self.showing = None;
+    }
+
+    /// Take down the message being shown and drop any not
+    /// yet shown. They are still in the history.
+    pub fn dismiss_all(&mut self) {
+        self.showing = None;
+        self.pending.clear();
+    }
+
+    /// The message to show now, if any. A message that has
+    /// been up long enough is taken down and the next one
+    /// put up.
+    pub fn current(&mut self) -> Option<&str> {
+        if let Some((_, shown)) = &self.showing
+            && shown.elapsed() >= OVERLAY_TIME
+        {
+            self.showing = None;
+        }
+        if self.showing.is_none() {
+            let text = self.pending.pop_front()?;
+            self.showing = Some((text, Instant::now()));
+        }
+        self.showing.as_ref().map(|(text, _)| text.as_str())
+    }
+
+    /// Take all messages not yet shown, for clients that
+    /// cannot show overlays.
+    pub fn drain(&mut self) -> impl Iterator<Item = String> + '_ {
+        self.showing = None;
+        self.pending.drain(..)
+    }
+
+    /// Recent messages, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &str> {
+        self.history.iter().map(String::as_str)
+    }
+}
+
+/// Overlay `text` in brackets on the display `line`, on the
+/// side away from the `cursor` column so that the hero stays
+/// in view. The text is cut to fit.
+pub fn overlay(line: &str, text: &str, cursor: usize) -> String {
+    let mut chars: Vec<char> = line.chars().collect();
+    let width = chars.len();
+    // Columns free for the overlay, leaving a gap beside
+    // the cursor.
+    let (start, end) = if cursor < width / 2 {
+        (cursor + 2, width)
+    } else {
+        (0, cursor.saturating_sub(1))
+    };
+    // The cursor may be off the end of the line.
+    let (start, end) = (start.min(width), end.min(width));
+    let room = end.saturating_sub(start);
+    if room < 3 {
+        return line.to_string();
+    }
+    let text: Vec<char> = text.chars().take(room - 2).collect();
+    let len = text.len() + 2;
+    // Put the overlay against the far edge of the line.
+    let at = if start == 0 { 0 } else { end - len };
+    chars[at] = '[';
+    chars[at + 1..at + len - 1].copy_from_slice(&text);
+    chars[at + len - 1] = ']';
+    chars.into_iter().collect()
+}
+
+/// Where a client shows the status display.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StatusArea {
+    /// Nowhere.
+    #[default]
+    None,
+    /// In a segment of this many characters at the right
+    /// end of the display line.
+    Segment(usize),
+    /// On a line of its own below the display line.
+    Line,
+}
+
+impl StatusArea {
+    /// Characters kept back from the board view for a
+    /// status segment. The segment is a third of the line,
+    /// up to this.
+    pub const SEGMENT: usize = 24;
+
+    /// Lay out a status display for the given client's
+    /// terminal. The status goes on a line of its own only
+    /// if the terminal is at least two lines high and either
+    /// is known to handle ANSI cursor movement or is in line
+    /// mode, where every line is printed afresh. Returns the
+    /// status area and the width left for the board view.
+    pub fn layout(remote: &Connection) -> (StatusArea, u16) {
+        let width = remote.width.unwrap_or(Player::WIDTH);
+        let tall = remote.height.is_some_and(|height| height >= 2);
+        if tall && (remote.is_ansi() || remote.line_mode) {
+            return (StatusArea::Line, width);
+        }
+        let segment = StatusArea::SEGMENT.min(width as usize / 3);
+        // The segment is set off from the board by a space.
+        let board = width as usize - segment - 1;
+        (StatusArea::Segment(segment), board as u16)
+    }
+}
+
+/// What goes on a display line.
+pub enum Showing<'a> {
+    /// A board view, with what each column shows and any
+    /// message to show along with it.
+    Board(String, Vec<Tile>, Option<&'a str>),
+    /// Plain text, such as a prompt.
+    Text(String),
+}
+
+/// What a client is currently showing on its display line,
+/// and status line if it has one.
+#[derive(Default)]
+pub struct Screen {
+    /// Where the status display goes.
+    area: StatusArea,
+    /// Line last sent.
+    line: String,
+    /// Status line last sent, if the status has a line.
+    status: String,
+    /// Cursor column last sent.
+    cursor: usize,
+    /// The line has been written over and must be redrawn.
+    stale: bool,
+}
+
+/// A way of showing a player's display on their client.
+/// [Screen] works on any terminal; see [screen_for] for the
+/// choice of renderer.
+pub trait Render {
+    /// Where the status display goes.
+    fn area(&self) -> StatusArea;
+
+    /// Show the display line with the cursor at column
+    /// `cursor`, and the `status` display, if that is not
+    /// what is already showing. In line mode the line is also
+    /// sent if the player just `acted`, since every view is a
+    /// fresh line.
+    fn draw(
+        &mut self,
+        remote: &mut Connection,
+        showing: Showing,
+        status: &str,
+        cursor: usize,
+        acted: bool,
+    );
+
+    /// Write `text` on a line of its own, apart from the
+    /// display line, which must then be redrawn.
+    fn print(&mut self, remote: &mut Connection, text: &str);
+
+    /// Show a final message overlaid on the display line and
+    /// leave the cursor on a fresh line below the display.
+    fn finish(&mut self, remote: &mut Connection, text: &str);
+
+    /// Forget what is showing, so that the next line drawn
+    /// is sent.
+    fn forget(&mut self);
+}
+
+/// A renderer for the given client, with the status display
+/// in the given area: the ANSI renderer if the client
+/// qualifies for it, else a plain [Screen].
+#[cfg_attr(not(feature = "ansi"), allow(unused_variables))]
+pub fn screen_for(remote: &mut Connection, area: StatusArea) -> Box<dyn Render> {
+    #[cfg(feature = "ansi")]
+    if let Some(screen) = AnsiScreen::new(remote, area) {
+        return Box::new(screen);
+    }
+    Box::new(Screen::new(area))
+}
+
+impl Screen {
+    /// A screen showing the status display in the given
+    /// area.
+    pub fn new(area: StatusArea) -> Self {
+        Screen {
+            area,
+            ..Screen::default()
+        }
+    }
+}
+
+impl Render for Screen {
+    fn area(&self) -> StatusArea {
+        self.area
+    }
+
+    fn draw(
+        &mut self,
+        remote: &mut Connection,
+        showing: Showing,
+        status: &str,
+        cursor: usize,
+        acted: bool,
+    ) {
+        let line = match showing {
+            Showing::Board(line, _, Some(text)) => overlay(&line, text, cursor),
+            Showing::Board(line, _, None) | Showing::Text(line) => line,
+        };
+        let (line, status) = match self.area {
+            StatusArea::None => (line, String::new()),
+            StatusArea::Segment(width) => {
+                (format!("{} {:width$.width$}", line, status), String::new())
+            }
+            StatusArea::Line => {
+                let width = line.chars().count();
+                let status = format!("{:width$.width$}", status);
+                (line, status)
+            }
+        };
+        let changed =
+            self.stale || cursor != self.cursor || line != self.line || status != self.status;
+        if remote.line_mode && (changed || acted) {
+            let _ = write!(remote, "{}\r\n", line);
+            if !status.is_empty() {
+                let _ = write!(remote, "{}\r\n", status);
+            }
+        } else if changed {
+            let end = line
+                .char_indices()
+                .nth(cursor)
+                .map_or(line.len(), |(i, _)| i);
+            let _ = write!(remote, "\r{}", line);
+            if self.area == StatusArea::Line {
+                // Down to the status line and back up.
+                let _ = write!(remote, "\r\n{}\x1b[A", status);
+            }
+            let _ = write!(remote, "\r{}", &line[..end]);
+        }
+        self.line = line;
+        self.status = status;
+        self.cursor = cursor;
+        self.stale = false;
+    }
+
+    fn print(&mut self, remote: &mut Connection, text: &str) {
+        let width = self.line.chars().count();
+        let _ = write!(remote, "\r{:width$}\r\n", text);
+        self.forget();
+    }
+
+    fn finish(&mut self, remote: &mut Connection, text: &str) {
+        let mut line = overlay(&self.line, text, self.cursor);
+        if remote.line_mode || line == self.line {
+            // No board to overlay, or no room on it.
+            line = text.to_string();
+        }
+        let width = self.line.chars().count();
+        let _ = write!(remote, "\r{:width$}\r\n", line);
+        if self.area == StatusArea::Line && !remote.line_mode {
+            let _ = write!(remote, "\r\n");
+        }
+        self.forget();
+    }
+
+    fn forget(&mut self) {
+        self.stale = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlay_avoids_cursor() {
+        let line = ".".repeat(20);
+        assert_eq!(
+            overlay(&line, "hello", 2),
+            format!("{}[hello]", ".".repeat(13))
+        );
+        assert_eq!(
+            overlay(&line, "hello", 15),
+            format!("[hello]{}", ".".repeat(13))
+        );
+    }
+
+    #[test]
+    fn status_line_needs_cursor_control() {
+        let (server, _client) = Pipe::pair();
+        let mut remote = Connection::new(Box::new(server));
+        remote.width = Some(60);
+        remote.height = Some(24);
+        assert_eq!(StatusArea::layout(&remote), (StatusArea::Segment(20), 39));
+        remote.line_mode = true;
+        assert_eq!(StatusArea::layout(&remote), (StatusArea::Line, 60));
+    }
+
+    #[test]
+    fn overlay_fits_the_line() {
+        let line = ".".repeat(20);
+        assert_eq!(
+            overlay(&line, "hello", 50),
+            format!("[hello]{}", ".".repeat(13))
+        );
+        let long = "x".repeat(40);
+        assert_eq!(overlay(&line, &long, 50), format!("[{}]", "x".repeat(18)));
+        assert_eq!(overlay(&line, &long, 0), format!("..[{}]", "x".repeat(16)));
+        assert_eq!(overlay("...", &long, 1), "...");
+    }
+}
@@ -9,24 +9,93 @@ pub struct Mob {
     pub id: u64,
     /// MOB position in field coordinates.
     pub posn: usize,
+    /// Where the MOB appeared.
+    pub home: usize,
     /// MOB health.
     health: u64,
+    /// MOB starting health.
+    max_health: u64,
+    /// What kind of monster this is.
+    pub kind: &'static MonsterKind,
+    /// This MOB is the boss guarding the door.
+    pub boss: bool,
 }
 
 impl Mob {
-    /// Make a new MOB with the given ID and position, and
-    /// with random health.
-    pub fn new(id: u64, posn: usize) -> Self {
+    /// Make a new MOB of the given kind with the given ID
+    /// and position, and with random health for its kind.
+    pub fn new(id: u64, posn: usize, kind: &'static MonsterKind, rng: &mut Rng) -> Self {
+        let health = random(rng, kind.max_health - kind.min_health + 1) + kind.min_health;
         Mob {
             id,
             posn,
-            health: random(3) + 3,
+            home: posn,
+            health,
+            max_health: health,
+            kind,
+            boss: false,
         }
     }
 
-    /// Take a hit.
-    pub fn hit(&mut self) -> bool {
-        let hit = random(3);
+    /// Make the boss, with the given ID and position.
+    pub fn boss(id: u64, posn: usize, rng: &mut Rng) -> Self {
+        Mob {
+            boss: true,
+            ..Mob::new(id, posn, boss_kind(), rng)
+        }
+    }
+
+    /// Saved-game text for the MOB: `ID POSN HOME HEALTH
+    /// MAX_HEALTH KIND`.
+    pub fn snapshot(&self) -> String {
+        format!(
+            "{} {} {} {} {} {}",
+            self.id, self.posn, self.home, self.health, self.max_health, self.kind.name,
+        )
+    }
+
+    /// Restore a MOB from its saved-game text. Its kind must
+    /// be in the monster table.
+    pub fn from_snapshot(text: &str) -> Result<Mob, String> {
+        // Kind names may have spaces, so the kind is the
+        // rest of the line.
+        let fields: Vec<&str> = text.splitn(6, ' ').collect();
+        let [id, posn, home, health, max_health, kind] = fields[..] else {
+            return Err(format!("expected 6 fields, got {}", fields.len()));
+        };
+        let kind = kinds()
+            .iter()
+            .find(|k| k.name == kind)
+            .ok_or_else(|| format!("unknown monster kind \"{}\"", kind))?;
+        Ok(Mob {
+            id: number(id)?,
+            posn: number(posn)?,
+            home: number(home)?,
+            health: number(health)?,
+            max_health: number(max_health)?,
+            kind,
+            boss: kind.name == boss_kind().name,
+        })
+    }
+
+    /// Remaining health.
+    pub fn health(&self) -> u64 {
+        self.health
+    }
+
+    /// Starting health.
+    pub fn max_health(&self) -> u64 {
+        self.max_health
+    }
+
+    /// The field object for this MOB.
+    pub fn object(&self) -> Object {
+        Object::Monster(self.id, self.kind.glyph)
+    }
+
+    /// Take a hit doing the given damage. Returns `false` if
+    /// the hit was fatal.
+    pub fn hit(&mut self, hit: u64) -> bool {
         if hit >= self.health {
             self.health = 0;
             false
@@ -36,21 +105,12 @@ impl Mob {
         }
     }
 
-    /// MOB "AI". Heuristically decide how to move the MOB.
-    /// Returns the new position.
-    pub fn get_move(&self) -> usize {
-        let posn = self.posn;
-        let dirn = random(3);
-        match dirn {
-            0 => {
-                if posn == 0 {
-                    return posn;
-                }
-                posn - 1
-            }
-            1 => posn,
-            2 => posn + 1,
-            _ => panic!("internal error: weird mob move"),
+    /// Decide how to move the MOB, according to its kind's
+    /// speed and AI. Returns the new position.
+    pub fn get_move(&self, senses: &Senses, rng: &mut Rng) -> usize {
+        if random(rng, 100) >= self.kind.speed {
+            return self.posn;
         }
+        self.kind.ai.ai().get_move(self, senses, rng)
     }
 }
@@ -0,0 +1,284 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! Monster kinds. The kinds of monster in the dungeon, and
+//! how each looks, fights, moves and where it turns up, come
+//! from a data file read once at startup. A built-in table
+//! is used if no file is configured.
+//!
+//! The file is a sequence of `[name]` stanzas, each followed
+//! by `key = value` lines giving `glyph`, `health` (a range
+//! `LOW-HIGH`), `damage`, `speed`, `ai` and `depth`. Blank
+//! lines and `#` comments are ignored. There must be a kind
+//! named `boss`: that is the monster guarding the door, and
+//! it is never spawned otherwise.
+
+use crate::*;
+
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// The built-in monster table.
+const DEFAULT_MONSTERS: &str = include_str!("monsters.txt");
+
+/// The installed monster table.
+static KINDS: OnceLock<Vec<MonsterKind>> = OnceLock::new();
+
+/// Name of the boss kind.
+const BOSS: &str = "boss";
+
+/// How a monster kind decides where to move. See
+/// [Behaviour::ai].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Behaviour {
+    /// Stagger about at random.
+    Wander,
+    /// Go after the nearest hero.
+    Chase,
+    /// Chase, but run away when badly wounded.
+    Flee,
+    /// Stay near home.
+    Guard,
+    /// Move as a pack with others of the kind.
+    Pack,
+}
+
+/// One kind of monster.
+#[derive(Debug, Clone)]
+pub struct MonsterKind {
+    /// Kind name.
+    pub name: String,
+    /// Display character.
+    pub glyph: char,
+    /// Least starting health.
+    pub min_health: u64,
+    /// Greatest starting health.
+    pub max_health: u64,
+    /// Damage done to the party by one blow.
+    pub damage: u64,
+    /// Percent chance of moving on any given turn.
+    pub speed: u64,
+    /// How the kind moves.
+    pub ai: Behaviour,
+    /// Percent of the way from the entrance to the door
+    /// where this kind is most common.
+    pub depth: u64,
+}
+
+/// Glyphs of the hero, rock, door and floor, which no
+/// monster may share. Nor may a monster look like an item.
+const RESERVED_GLYPHS: [char; 4] = ['@', '#', '+', '.'];
+
+/// Kinds are most common at their depth, and rarer up to
+/// this many percent of the way to the door either side of
+/// it.
+const DEPTH_SPREAD: u64 = 50;
+
+/// Set the field named `key` of `kind` from the text
+/// `value`.
+fn set_field(kind: &mut MonsterKind, key: &str, value: &str) -> Result<(), String> {
+    let num = |value: &str| number(value).map_err(|e| format!("{}: {}", key, e));
+    match key {
+        "glyph" => {
+            let mut chars = value.chars();
+            kind.glyph = match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_graphic() => c,
+                _ => return Err(format!("glyph: expected one character, got \"{}\"", value)),
+            };
+        }
+        "health" => {
+            let (low, high) = value.split_once('-').unwrap_or((value, value));
+            kind.min_health = num(low.trim())?;
+            kind.max_health = num(high.trim())?;
+        }
+        "damage" => kind.damage = num(value)?,
+        "speed" => kind.speed = num(value)?,
+        "ai" => {
+            kind.ai = match value {
+                "wander" => Behaviour::Wander,
+                "chase" => Behaviour::Chase,
+                "flee" => Behaviour::Flee,
+                "guard" => Behaviour::Guard,
+                "pack" => Behaviour::Pack,
+                _ => return Err(format!("ai: unknown behaviour \"{}\"", value)),
+            };
+        }
+        "depth" => kind.depth = num(value)?,
+        _ => return Err(format!("unknown setting \"{}\"", key)),
+    }
+    Ok(())
+}
+
+/// Check that a kind is usable.
+fn check_kind(kind: &MonsterKind) -> Result<(), String> {
+    if kind.glyph == ' ' {
+        return Err(format!("{}: missing glyph", kind.name));
+    }
+    if RESERVED_GLYPHS.contains(&kind.glyph)
+        || Item::ALL.iter().any(|item| item.render() == kind.glyph)
+    {
+        return Err(format!("{}: glyph {} is taken", kind.name, kind.glyph));
+    }
+    if kind.min_health == 0 || kind.min_health > kind.max_health {
+        return Err(format!("{}: bad health range", kind.name));
+    }
+    if kind.speed > 100 {
+        return Err(format!("{}: speed is a percentage", kind.name));
+    }
+    if kind.depth > 100 {
+        return Err(format!("{}: depth is a percentage", kind.name));
+    }
+    if kind.name == BOSS && kind.speed != 0 {
+        return Err(format!(
+            "{}: the boss never moves: speed must be 0",
+            kind.name
+        ));
+    }
+    Ok(())
+}
+
+/// Parse monster table text. `path` is for error messages.
+pub fn parse_kinds(path: &str, text: &str) -> io::Result<Vec<MonsterKind>> {
+    let mut kinds: Vec<MonsterKind> = Vec::new();
+    for (lineno, line) in content_lines(text) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let name = name.trim();
+            if let Some(kind) = kinds.last() {
+                check_kind(kind).map_err(|e| bad(path, lineno, &e))?;
+            }
+            if kinds.iter().any(|kind| kind.name == name) {
+                return Err(bad(path, lineno, "duplicate kind"));
+            }
+            kinds.push(MonsterKind {
+                name: name.to_string(),
+                glyph: ' ',
+                min_health: 1,
+                max_health: 1,
+                damage: 1,
+                speed: 100,
+                ai: Behaviour::Wander,
+                depth: 0,
+            });
+            continue;
+        }
+        let Some(kind) = kinds.last_mut() else {
+            return Err(bad(path, lineno, "setting outside of a [kind]"));
+        };
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| bad(path, lineno, "expected key = value"))?;
+        set_field(kind, key.trim(), value.trim()).map_err(|e| bad(path, lineno, &e))?;
+    }
+    let nlines = text.lines().count();
+    if let Some(kind) = kinds.last() {
+        check_kind(kind).map_err(|e| bad(path, nlines, &e))?;
+    }
+    if !kinds.iter().any(|kind| kind.name == BOSS) {
+        return Err(bad(path, nlines, "no boss kind"));
+    }
+    if !kinds.iter().any(|kind| kind.name != BOSS) {
+        return Err(bad(path, nlines, "no kinds but the boss"));
+    }
+    let boss = kinds.iter().find(|kind| kind.name == BOSS).unwrap();
+    if let Some(kind) = kinds
+        .iter()
+        .find(|kind| kind.name != BOSS && kind.glyph == boss.glyph)
+    {
+        let msg = format!("{}: glyph {} is the boss's", kind.name, kind.glyph);
+        return Err(bad(path, nlines, &msg));
+    }
+    Ok(kinds)
+}
+
+/// Read the monster table from the given file, or use the
+/// built-in table, and make it the one returned by
+/// [kinds()]. Fails if the file is bad or a table is
+/// already installed.
+pub fn install_kinds(path: Option<&Path>) -> io::Result<()> {
+    let kinds = match path {
+        Some(path) => {
+            let text = fs::read_to_string(path)?;
+            parse_kinds(&path.display().to_string(), &text)?
+        }
+        None => parse_kinds("built-in monsters", DEFAULT_MONSTERS)?,
+    };
+    KINDS
+        .set(kinds)
+        .map_err(|_| io::Error::other("monster table already installed"))
+}
+
+/// Get the installed monster table, or the built-in one if
+/// none has been installed.
+pub fn kinds() -> &'static [MonsterKind] {
+    KINDS.get_or_init(|| parse_kinds("built-in monsters", DEFAULT_MONSTERS).unwrap())
+}
+
+/// The boss kind.
+pub fn boss_kind() -> &'static MonsterKind {
+    kinds().iter().find(|kind| kind.name == BOSS).unwrap()
+}
+
+/// Pick a kind of monster to spawn at the given field
+/// position, favoring kinds whose depth is near the
+/// position's.
+pub fn choose_kind(posn: usize, rng: &mut Rng) -> &'static MonsterKind {
+    let depth = (posn * 100 / config().door_posn).min(100) as u64;
+    let weight = |kind: &MonsterKind| DEPTH_SPREAD.saturating_sub(kind.depth.abs_diff(depth));
+    let candidates: Vec<&MonsterKind> = kinds().iter().filter(|kind| kind.name != BOSS).collect();
+    let total: u64 = candidates.iter().map(|&kind| weight(kind)).sum();
+    if total == 0 {
+        // Nothing is at home here: take the nearest.
+        return candidates
+            .into_iter()
+            .min_by_key(|kind| kind.depth.abs_diff(depth))
+            .unwrap();
+    }
+    let mut pick = random(rng, total);
+    for kind in candidates {
+        let w = weight(kind);
+        if pick < w {
+            return kind;
+        }
+        pick -= w;
+    }
+    unreachable!("monster weights changed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A monster table with the given extra settings for
+    /// the boss, and another kind with the given glyph.
+    fn table(boss: &str, glyph: char) -> String {
+        format!(
+            "[rat]\nglyph = {}\nhealth = 1-2\n[boss]\nglyph = B\nhealth = 30\nspeed = 0\n{}\n",
+            glyph, boss,
+        )
+    }
+
+    #[test]
+    fn built_in_table_loads() {
+        assert!(parse_kinds("built-in", DEFAULT_MONSTERS).is_ok());
+        assert!(parse_kinds("test", &table("", 'r')).is_ok());
+    }
+
+    #[test]
+    fn boss_must_stand_still() {
+        assert!(parse_kinds("test", &table("speed = 10", 'r')).is_err());
+    }
+
+    #[test]
+    fn reserved_glyphs_are_rejected() {
+        for glyph in RESERVED_GLYPHS {
+            assert!(parse_kinds("test", &table("", glyph)).is_err());
+        }
+        for item in Item::ALL {
+            assert!(parse_kinds("test", &table("", item.render())).is_err());
+        }
+        assert!(parse_kinds("test", &table("", 'B')).is_err());
+    }
+}
@@ -1,20 +1,22 @@
 //! Player avatar state and implementation. This is only
 //! those attributes unique to each client.
 
+use crate::{Messages, config, number};
+
 /// Player avatar state.
 pub struct Player {
     /// Player id. Globally unique across all sessions.
     pub id: u64,
+    /// Name the player's hero goes by.
+    pub name: String,
     /// Position in field coordinates.
     pub posn: usize,
     /// Terminal line width in characters.
     pub width: u16,
     /// Offset of player in terminal view in range 0..width.
     pub left: usize,
-    /// Player prior terminal view line. Used for refresh.
-    pub display_cache: String,
-    /// Player prior terminal position. Used for refresh.
-    pub posn_cache: usize,
+    /// Messages for the player.
+    pub messages: Messages,
 }
 
 /// Offset `x` by `dx`. Return `None` if the offset would be
@@ -28,28 +30,79 @@ pub fn offset(x: usize, dx: isize) -> Option<usize> {
 }
 
 impl Player {
-    /// Desired left/right margin on terminal in characters.
+    /// Default desired left/right margin on terminal in
+    /// characters. See [crate::ServerConfig::margin].
     pub const MARGIN: usize = 3;
 
-    /// Make a new player state with the given `id` and `width`.
-    pub fn new(id: u64, width: Option<u16>) -> Self {
+    /// Default terminal width in characters, for clients
+    /// that do not say.
+    pub const WIDTH: u16 = 80;
+
+    /// Make a new player state with the given `id`, `name`
+    /// and `width`. A hero without a name is known by their
+    /// `id`.
+    pub fn new(id: u64, name: Option<&str>, width: Option<u16>) -> Self {
         Player {
             id,
+            name: name.map_or_else(|| format!("hero {}", id), String::from),
             posn: 1,
             left: 1,
-            width: width.unwrap_or(80),
-            display_cache: String::new(),
-            posn_cache: 0,
+            width: width.unwrap_or(Player::WIDTH),
+            messages: Messages::default(),
         }
     }
 
+    /// Saved-game text for the player's hero: `ID POSN WIDTH
+    /// LEFT NAME`. Messages are not saved.
+    pub fn snapshot(&self) -> String {
+        format!(
+            "{} {} {} {} {}",
+            self.id, self.posn, self.width, self.left, self.name,
+        )
+    }
+
+    /// Restore a player's hero from its saved-game text. The
+    /// view is clamped to keep the hero in it.
+    pub fn from_snapshot(text: &str) -> Result<Player, String> {
+        // Names of heroes without accounts have spaces, so
+        // the name is the rest of the line.
+        let fields: Vec<&str> = text.splitn(5, ' ').collect();
+        let [id, posn, width, left, name] = fields[..] else {
+            return Err(format!("expected 5 fields, got {}", fields.len()));
+        };
+        let width = width
+            .parse::<u16>()
+            .map_err(|_| format!("invalid width \"{}\"", width))?;
+        let posn = number(posn)?;
+        Ok(Player {
+            posn,
+            left: number::<usize>(left)?.min(posn),
+            ..Player::new(number(id)?, Some(name), Some(width))
+        })
+    }
+
+    /// Change the terminal view width, keeping the player
+    /// in view and inside the margins if possible.
+    pub fn resize(&mut self, width: u16) {
+        self.width = width;
+        let margin = config().margin;
+        self.left = self
+            .left
+            .min((width as usize).saturating_sub(margin))
+            .max(margin)
+            .min(self.posn);
+    }
+
     /// Slide the terminal view window for the player to
     /// where it is supposed to be.
     pub fn adjust_display(&mut self, dirn: isize) {
         if let Some(posn) = offset(self.posn, dirn) {
             if let Some(left) = offset(self.left, dirn) {
-                let margin = Player::MARGIN;
-                self.left = left.min(self.width as usize - margin).max(margin).min(posn);
+                let margin = config().margin;
+                self.left = left
+                    .min((self.width as usize).saturating_sub(margin))
+                    .max(margin)
+                    .min(posn);
             } else {
                 self.left = 0;
             }
@@ -0,0 +1,405 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! Registry of running game instances, and the text lobby
+//! in which a connected player signs in and picks a game to
+//! play.
+
+use crate::*;
+
+/// Longest allowed game or hero name in characters.
+const MAX_NAME_LEN: usize = 16;
+
+/// Lobby help text.
+const LOBBY_HELP: &[&str] = &[
+    "commands:",
+    "  l         list games",
+    "  c [name]  create a new game and join it",
+    "  j name    join a game",
+    "  w [name]  watch a game without playing",
+    "  k [keys]  show or choose key bindings: vi, arrows or wasd",
+    "  s         show your record, high scores and recent games",
+    "  q         disconnect",
+    "  (enter)   join any game, or start one",
+];
+
+/// Show the lobby help text.
+fn show_help(remote: &mut Connection) {
+    let _ = write!(remote, "\r\n");
+    for line in LOBBY_HELP {
+        let _ = write!(remote, "{}\r\n", line);
+    }
+}
+
+/// All the games currently running on the server, by name.
+#[derive(Default, Clone)]
+pub struct GameRegistry(Arc<Mutex<Registry>>);
+
+/// Registry state behind the lock.
+#[derive(Default)]
+struct Registry {
+    /// Games by name.
+    games: HashMap<String, GameHandle>,
+    /// Serial number for the *next* unnamed game.
+    next_serial: u64,
+    /// Names of the players signed in.
+    online: HashSet<String>,
+}
+
+impl Registry {
+    /// Drop games that have finished.
+    fn reap(&mut self) {
+        self.games.retain(|name, handle| {
+            let over = handle.is_over();
+            if over {
+                println!("game over: {}", name);
+            }
+            !over
+        });
+    }
+}
+
+/// Is `name` acceptable as a game or hero name?
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl GameRegistry {
+    /// Execute some registry code under the registry lock,
+    /// after reaping finished games.
+    fn with_registry<T>(&self, action: impl FnOnce(&mut Registry) -> T) -> T {
+        let mut registry = self.0.lock().unwrap();
+        registry.reap();
+        action(&mut registry)
+    }
+
+    /// Names and hero counts of the running games, sorted
+    /// by name.
+    pub fn list(&self) -> Vec<(String, usize)> {
+        let mut games: Vec<(String, usize)> = self.with_registry(|registry| {
+            registry
+                .games
+                .iter()
+                .map(|(name, handle)| (name.clone(), handle.nplayers()))
+                .collect()
+        });
+        games.sort();
+        games
+    }
+
+    /// Start a new game. If `name` is `None` a fresh name is
+    /// made up. Returns the name and handle, or `None` if
+    /// the name is taken.
+    pub fn create(&self, name: Option<&str>) -> Option<(String, GameHandle)> {
+        self.with_registry(|registry| {
+            let name = match name {
+                Some(name) => name.to_string(),
+                None => loop {
+                    registry.next_serial += 1;
+                    let name = format!("game{}", registry.next_serial);
+                    if !registry.games.contains_key(&name) {
+                        break name;
+                    }
+                },
+            };
+            if registry.games.contains_key(&name) {
+                return None;
+            }
+            let handle = GameHandle::default();
+            registry.games.insert(name.clone(), handle.clone());
+            println!("new game: {} seed {}", name, handle.seed());
+            Some((name, handle))
+        })
+    }
+
+    /// Put resumed games in the registry.
+    pub fn resume(&self, games: Vec<(String, Game)>) {
+        self.with_registry(|registry| {
+            for (name, game) in games {
+                println!("resumed game: {} seed {}", name, game.seed);
+                registry.games.insert(name, GameHandle::resume(game));
+            }
+        });
+    }
+
+    /// Saved-game text for every running game, as the save
+    /// file holds it.
+    pub fn snapshot(&self) -> String {
+        let mut games: Vec<(String, GameHandle)> = self.with_registry(|registry| {
+            registry
+                .games
+                .iter()
+                .map(|(name, handle)| (name.clone(), handle.clone()))
+                .collect()
+        });
+        games.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut text = String::from("# one-way-out saved games\n");
+        for (name, handle) in games {
+            if let Some(game) = handle.snapshot() {
+                text += &format!("[{}]\n{}", name, game);
+            }
+        }
+        text
+    }
+
+    /// The running game in which the named player's hero is
+    /// away, if any.
+    pub fn waiting(&self, name: &str) -> Option<(String, GameHandle)> {
+        self.with_registry(|registry| {
+            registry
+                .games
+                .iter()
+                .find(|(_, handle)| handle.is_waiting(name))
+                .map(|(game, handle)| (game.clone(), handle.clone()))
+        })
+    }
+
+    /// Sign in the named player. Returns `false` if they
+    /// are signed in already.
+    fn sign_in(&self, name: &str) -> bool {
+        self.with_registry(|registry| registry.online.insert(name.to_string()))
+    }
+
+    /// Sign out the named player.
+    fn sign_out(&self, name: &str) {
+        self.with_registry(|registry| registry.online.remove(name));
+    }
+
+    /// Ask the client for their hero's name and, if their
+    /// account has one, their password. A new name makes a
+    /// new account, with a password if the player gives one.
+    /// The name the client offered is the default. Returns
+    /// the name the player signed in with, or `None` if the
+    /// connection was lost.
+    fn login(&self, remote: &mut Connection) -> Option<String> {
+        let offered = remote.name.take().filter(|name| valid_name(name));
+        loop {
+            match &offered {
+                Some(name) => {
+                    let _ = write!(remote, "\r\nname [{}]: ", name);
+                }
+                None => {
+                    let _ = write!(remote, "\r\nname: ");
+                }
+            }
+            let line = remote.read_line().ok()?;
+            let name = match (line.trim(), &offered) {
+                ("", Some(name)) => name.clone(),
+                ("", None) => continue,
+                (name, _) => name.to_string(),
+            };
+            if !valid_name(&name) {
+                let _ = write!(
+                    remote,
+                    "\r\nnames are up to {} letters, digits, - or _\r\n",
+                    MAX_NAME_LEN,
+                );
+                continue;
+            }
+            let greeting = match account(&name) {
+                Some(account) => {
+                    if account.has_password() {
+                        let _ = write!(remote, "\r\npassword: ");
+                        let password = remote.read_password().ok()?;
+                        if !check_password(&name, &password) {
+                            let _ = write!(remote, "\r\nwrong password\r\n");
+                            continue;
+                        }
+                    }
+                    format!("welcome back, {}: {}", name, account.summary())
+                }
+                None => {
+                    let _ = write!(remote, "\r\nnew hero {}. password (Enter for none): ", name,);
+                    let password = remote.read_password().ok()?;
+                    let password = Some(password.trim()).filter(|p| !p.is_empty());
+                    match create_account(&name, password) {
+                        Ok(true) => (),
+                        Ok(false) => {
+                            let _ = write!(remote, "\r\nthat name was just taken\r\n");
+                            continue;
+                        }
+                        Err(e) => eprintln!("cannot save account {}: {}", name, e),
+                    }
+                    format!("welcome, {}", name)
+                }
+            };
+            if !self.sign_in(&name) {
+                let _ = write!(remote, "\r\nthat hero is already playing\r\n");
+                continue;
+            }
+            let _ = write!(remote, "\r\n{}\r\n", greeting);
+            println!("signed in: {}", name);
+            return Some(name);
+        }
+    }
+
+    /// Find a running game by name.
+    pub fn get(&self, name: &str) -> Option<GameHandle> {
+        self.with_registry(|registry| registry.games.get(name).cloned())
+    }
+
+    /// Find the running game with the most heroes, if any.
+    pub fn busiest(&self) -> Option<(String, GameHandle)> {
+        self.with_registry(|registry| {
+            registry
+                .games
+                .iter()
+                .max_by_key(|(_, handle)| handle.nplayers())
+                .map(|(name, handle)| (name.clone(), handle.clone()))
+        })
+    }
+
+    /// Find the running game with the most heroes, or
+    /// start a new one if there are none.
+    pub fn any(&self) -> (String, GameHandle) {
+        self.busiest().unwrap_or_else(|| self.create(None).unwrap())
+    }
+
+    /// The lobby for a connected client. The client signs
+    /// in, then chooses a game and plays it, returning here
+    /// when done, until they disconnect.
+    pub fn lobby(self, mut remote: Connection) {
+        let _ = write!(remote, "\r\nWelcome to One Way Out.\r\n");
+        let Some(name) = self.login(&mut remote) else {
+            return;
+        };
+        remote.name = Some(name.clone());
+        if let Some((game, _)) = self.waiting(&name) {
+            let _ = write!(
+                remote,
+                "your hero is waiting in {}: press Enter to go back\r\n",
+                game,
+            );
+        }
+        show_help(&mut remote);
+        self.choose(&mut remote);
+        self.sign_out(&name);
+        println!("signed out: {}", name);
+    }
+
+    /// The lobby loop for a signed-in client. Returns when
+    /// they disconnect.
+    fn choose(&self, remote: &mut Connection) {
+        loop {
+            let _ = write!(remote, "\r\nlobby> ");
+            let line = match remote.read_line() {
+                Ok(line) => line,
+                Err(e) => {
+                    eprintln!("net read error: {}", e);
+                    return;
+                }
+            };
+            let mut words = line.split_whitespace();
+            let cmd = words.next();
+            let arg = words.next();
+            let game = match (cmd, arg) {
+                (None, _) => {
+                    let waiting = remote.name.as_deref().and_then(|name| self.waiting(name));
+                    Some(waiting.unwrap_or_else(|| self.any()))
+                }
+                (Some("l"), None) => {
+                    let games = self.list();
+                    if games.is_empty() {
+                        let _ = write!(remote, "\r\nno games running\r\n");
+                    }
+                    for (name, nplayers) in games {
+                        let _ = write!(remote, "\r\n  {:16} {} heroes", name, nplayers);
+                    }
+                    let _ = write!(remote, "\r\n");
+                    None
+                }
+                (Some("c"), name) => {
+                    if name.is_some_and(|name| !valid_name(name)) {
+                        let _ = write!(
+                            remote,
+                            "\r\nnames are up to {} letters, digits, - or _\r\n",
+                            MAX_NAME_LEN,
+                        );
+                        continue;
+                    }
+                    let game = self.create(name);
+                    if game.is_none() {
+                        let _ = write!(remote, "\r\nthat name is taken\r\n");
+                    }
+                    game
+                }
+                (Some("j"), Some(name)) => {
+                    let game = self.get(name);
+                    if game.is_none() {
+                        let _ = write!(remote, "\r\nno such game\r\n");
+                    }
+                    game.map(|handle| (name.to_string(), handle))
+                }
+                (Some("w"), name) => {
+                    let game = match name {
+                        Some(name) => self.get(name).map(|handle| (name.to_string(), handle)),
+                        None => self.busiest(),
+                    };
+                    match game {
+                        Some((name, handle)) => {
+                            let _ = write!(remote, "\r\nwatching {}\r\n", name);
+                            if !handle.watch(remote) {
+                                return;
+                            }
+                        }
+                        None => {
+                            let _ = write!(remote, "\r\nno such game\r\n");
+                        }
+                    }
+                    None
+                }
+                (Some("k"), name) => {
+                    match name.map(Keymap::from_name) {
+                        None => (),
+                        Some(Some(keymap)) => remote.keymap = keymap,
+                        Some(None) => {
+                            let _ = write!(remote, "\r\nno such key bindings\r\n");
+                            continue;
+                        }
+                    }
+                    let keymap = remote.keymap;
+                    let _ = write!(remote, "\r\nkeys {}: {}\r\n", keymap.name(), keymap.help());
+                    None
+                }
+                (Some("s" | "scores"), None) => {
+                    let _ = write!(remote, "\r\n");
+                    if let Some(account) = remote.name.as_deref().and_then(account) {
+                        let _ = write!(remote, "{}: {}\r\n", account.name, account.summary());
+                    }
+                    match load_scores(&config().score_file) {
+                        Ok(scores) => {
+                            for line in leaderboards(&scores) {
+                                let _ = write!(remote, "{}\r\n", line);
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("cannot read scores: {}", e);
+                            let _ = write!(remote, "scores are not available\r\n");
+                        }
+                    }
+                    None
+                }
+                (Some("q"), None) => {
+                    let _ = write!(remote, "\r\nbye\r\n");
+                    return;
+                }
+                _ => {
+                    show_help(remote);
+                    None
+                }
+            };
+            if let Some((name, handle)) = game {
+                let _ = write!(remote, "\r\njoining {} (seed {})\r\n", name, handle.seed());
+                if !handle.play(remote) {
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,338 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! Game replay recording and playback. A game is fully
+//! determined by its seed, its tuning settings and the
+//! sequence of player joins and actions, along with the
+//! times every hero went away when the game was saved and
+//! resumed and each hero's return, so that is all a
+//! recording holds. Playback rebuilds the [Game] from the
+//! recording turn by turn.
+//!
+//! A replay file is text. A header of `key = value` settings
+//! (as in the config file) is followed by one line per
+//! entry: `join TURN PLAYER WIDTH`, `act TURN PLAYER
+//! ACTION`, `away TURN 0 -` or `back TURN PLAYER WIDTH`.
+
+use crate::*;
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A recorded change to the game from outside.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entry {
+    /// A player joined with the given terminal width.
+    Join(Option<u16>),
+    /// A player acted.
+    Act(Action),
+    /// Every hero went away: the game was resumed from a
+    /// save.
+    Away,
+    /// An away hero came back with the given terminal width.
+    Back(Option<u16>),
+}
+
+/// One line of a recording: the game turn at which a
+/// player did something, and what they did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    /// Game clock when the entry happened.
+    pub turn: u64,
+    /// Player concerned.
+    pub player_id: u64,
+    /// What happened.
+    pub entry: Entry,
+}
+
+impl Record {
+    /// Replay file line for this record, without the
+    /// newline.
+    pub fn format(&self) -> String {
+        let show_width = |width: Option<u16>| width.map_or("-".to_string(), |w| w.to_string());
+        match self.entry {
+            Entry::Join(width) => {
+                format!(
+                    "join {} {} {}",
+                    self.turn,
+                    self.player_id,
+                    show_width(width)
+                )
+            }
+            Entry::Away => format!("away {} {} -", self.turn, self.player_id),
+            Entry::Back(width) => {
+                format!(
+                    "back {} {} {}",
+                    self.turn,
+                    self.player_id,
+                    show_width(width)
+                )
+            }
+            Entry::Act(action) => format!(
+                "act {} {} {}",
+                self.turn,
+                self.player_id,
+                action_name(action),
+            ),
+        }
+    }
+
+    /// Parse a replay file entry line.
+    pub fn parse(line: &str) -> Result<Record, &'static str> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [kind, turn, player_id, arg] = fields[..] else {
+            return Err("expected four fields");
+        };
+        let turn = turn.parse().map_err(|_| "bad turn")?;
+        let player_id = player_id.parse().map_err(|_| "bad player")?;
+        let width = || -> Result<Option<u16>, &'static str> {
+            if arg == "-" {
+                return Ok(None);
+            }
+            Ok(Some(arg.parse().map_err(|_| "bad width")?))
+        };
+        let entry = match kind {
+            "join" => Entry::Join(width()?),
+            "away" if arg == "-" => Entry::Away,
+            "back" => Entry::Back(width()?),
+            "act" => Entry::Act(parse_action(arg).ok_or("bad action")?),
+            _ => return Err("unknown entry"),
+        };
+        Ok(Record {
+            turn,
+            player_id,
+            entry,
+        })
+    }
+}
+
+/// Settings saved in a replay header: those that change how
+/// a game plays out.
+const HEADER_KEYS: &[&str] = &[
+    "door_posn",
+    "max_health",
+    "margin",
+    "spawn_tiles",
+    "spawn_turns",
+    "item_turns",
+    "monsters",
+];
+
+/// Replay file name for action codes.
+fn action_name(action: Action) -> &'static str {
+    match action {
+        Action::Move(Dir::Left) => "left",
+        Action::Move(Dir::Right) => "right",
+        Action::Rest => "rest",
+        Action::PickUp => "pickup",
+        Action::Drop => "drop",
+        Action::Use => "use",
+        Action::Quit => "quit",
+    }
+}
+
+/// Action for a replay file action code.
+fn parse_action(name: &str) -> Option<Action> {
+    let action = match name {
+        "left" => Action::Move(Dir::Left),
+        "right" => Action::Move(Dir::Right),
+        "rest" => Action::Rest,
+        "pickup" => Action::PickUp,
+        "drop" => Action::Drop,
+        "use" => Action::Use,
+        "quit" => Action::Quit,
+        _ => return None,
+    };
+    Some(action)
+}
+
+/// Format a recording as replay file text.
+fn format_replay(seed: u64, log: &[Record]) -> String {
+    let config = config();
+    let mut text = String::from("# one-way-out replay\n");
+    text += &format!("seed = {}\n", seed);
+    text += &format!("door_posn = {}\n", config.door_posn);
+    text += &format!("max_health = {}\n", config.max_health);
+    text += &format!("margin = {}\n", config.margin);
+    text += &format!("spawn_tiles = {}\n", config.spawn_tiles);
+    text += &format!("spawn_turns = {}\n", config.spawn_turns);
+    text += &format!("item_turns = {}\n", config.item_turns);
+    if let Some(monsters) = &config.monsters {
+        text += &format!("monsters = {}\n", monsters.display());
+    }
+    for record in log {
+        text += &record.format();
+        text.push('\n');
+    }
+    text
+}
+
+/// Write a finished game's recording into the configured
+/// replay directory, if there is one. Returns the path
+/// written.
+pub fn save_replay(seed: u64, log: &[Record]) -> io::Result<Option<PathBuf>> {
+    let Some(dir) = &config().replay_dir else {
+        return Ok(None);
+    };
+    fs::create_dir_all(dir)?;
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |t| t.as_secs());
+    let path = dir.join(format!("game-{}-{}.replay", secs, seed));
+    fs::write(&path, format_replay(seed, log))?;
+    Ok(Some(path))
+}
+
+/// A parsed replay file.
+pub struct Replay {
+    /// Game seed.
+    pub seed: u64,
+    /// Game settings.
+    pub config: ServerConfig,
+    /// Recorded entries, in order.
+    pub log: Vec<Record>,
+}
+
+impl Replay {
+    /// Read a replay file.
+    pub fn load(path: &Path) -> io::Result<Replay> {
+        let text = fs::read_to_string(path)?;
+        let mut seed = None;
+        let mut config = ServerConfig::default();
+        let mut log = Vec::new();
+        for (lineno, line) in content_lines(&text) {
+            if let Some((key, value)) = line.split_once('=') {
+                let (key, value) = (key.trim(), value.trim());
+                if key == "seed" {
+                    let value = number(value)
+                        .map_err(|e| bad(path.display(), lineno, &format!("seed: {}", e)))?;
+                    seed = Some(value);
+                } else if HEADER_KEYS.contains(&key) {
+                    config
+                        .set(key, value)
+                        .map_err(|e| bad(path.display(), lineno, &e.to_string()))?;
+                } else {
+                    return Err(bad(path.display(), lineno, "unknown setting"));
+                }
+                continue;
+            }
+            log.push(Record::parse(line).map_err(|e| bad(path.display(), lineno, e))?);
+        }
+        let seed = seed.ok_or_else(|| bad(path.display(), 0, "missing seed"))?;
+        config
+            .validate()
+            .map_err(|e| bad(path.display(), 0, &e.to_string()))?;
+        Ok(Replay { seed, config, log })
+    }
+}
+
+/// Replay subcommand usage message.
+pub const REPLAY_USAGE: &str = "\
+usage: one-way-out replay FILE [--speed MS]
+  --speed MS          milliseconds per step (default 200)";
+
+/// Apply one recorded entry to the game being replayed.
+/// Fails if the recording does not fit the game: a join
+/// gets a different player ID, a player acts who is not in
+/// the game, or a hero comes back who was not away.
+pub fn replay_record(game: &mut Game, record: &Record) -> io::Result<Vec<GameEvent>> {
+    let diverged =
+        |msg: &str| io::Error::new(ErrorKind::InvalidData, format!("replay diverged: {}", msg));
+    match record.entry {
+        Entry::Join(width) => {
+            if game.join(None, width) != Some(record.player_id) {
+                return Err(diverged("unexpected player id"));
+            }
+            Ok(Vec::new())
+        }
+        Entry::Act(action) => {
+            if !game.players.contains_key(&record.player_id) {
+                let msg = format!("player {} is not in the game", record.player_id);
+                return Err(diverged(&msg));
+            }
+            Ok(game.apply(record.player_id, action))
+        }
+        Entry::Away => {
+            game.send_away();
+            Ok(Vec::new())
+        }
+        Entry::Back(width) => {
+            if !game.come_back(record.player_id, width) {
+                let msg = format!("player {} is not away", record.player_id);
+                return Err(diverged(&msg));
+            }
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// Play back a replay file to the local terminal: the
+/// arguments are those following the `replay` subcommand.
+/// The view follows the leading hero.
+pub fn replay_main(args: &[String]) -> io::Result<()> {
+    let usage = || io::Error::new(ErrorKind::InvalidInput, REPLAY_USAGE);
+    let (path, speed) = match args {
+        [path] => (path, 200),
+        [path, flag, ms] if flag == "--speed" => (path, ms.parse().map_err(|_| usage())?),
+        _ => return Err(usage()),
+    };
+    let replay = Replay::load(Path::new(path))?;
+    install_kinds(replay.config.monsters.as_deref())?;
+    replay
+        .config
+        .install()
+        .map_err(|e| io::Error::other(e.to_string()))?;
+
+    let mut game = Game::new(replay.seed);
+    let mut stdout = io::stdout();
+    let mut view = String::new();
+    for record in &replay.log {
+        let events = replay_record(&mut game, record)?;
+
+        // Follow the leading hero.
+        if let Some((render, _)) = game.leader().and_then(|id| game.view(id)) {
+            view = render;
+        }
+        write!(
+            stdout,
+            "\r{} turn {:5} health {:3}",
+            view, game.turns, game.health
+        )?;
+        stdout.flush()?;
+        for event in events {
+            let message = match event {
+                GameEvent::Quit => "quit",
+                GameEvent::Abandoned => "last hero quit",
+                GameEvent::Wiped => "board wipe",
+                GameEvent::Escaped => "escaped",
+                GameEvent::Won => "all escaped",
+                _ => continue,
+            };
+            writeln!(stdout, "\r\nplayer {}: {}", record.player_id, message)?;
+        }
+        std::thread::sleep(Duration::from_millis(speed));
+    }
+    writeln!(stdout)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acts_need_a_joined_player() {
+        let mut game = Game::new(7);
+        let act = Record::parse("act 0 1 right").unwrap();
+        assert!(replay_record(&mut game, &act).is_err());
+        let join = Record::parse("join 0 1 40").unwrap();
+        assert_eq!(replay_record(&mut game, &join).unwrap(), []);
+        assert!(!replay_record(&mut game, &act).unwrap().is_empty());
+        let stranger = Record::parse("act 1 2 rest").unwrap();
+        assert!(replay_record(&mut game, &stranger).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! High scores and run history. Each finished game adds a
+//! [Score] line to the configured score file, which is read
+//! back to show leaderboards in the lobby and from the
+//! command line.
+//!
+//! The score file is text, one game per line: `TIME SEED
+//! OUTCOME TURNS ESCAPED KILLS DAMAGE HEROES`, where `TIME`
+//! is in seconds since the Unix epoch and `HEROES` is the
+//! rest of the line, the heroes' names separated by commas.
+//! Blank lines and `#` comments are ignored, and bad lines
+//! are reported and skipped.
+
+use crate::*;
+
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many games each leaderboard shows.
+const LEADERBOARD_LEN: usize = 5;
+
+/// How a game ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The last hero in the game escaped.
+    Won,
+    /// The party's health ran out.
+    Wiped,
+    /// The last hero in the game quit.
+    Abandoned,
+}
+
+impl Outcome {
+    /// Name of the outcome, for the score file and
+    /// leaderboards.
+    pub fn name(self) -> &'static str {
+        match self {
+            Outcome::Won => "won",
+            Outcome::Wiped => "wiped",
+            Outcome::Abandoned => "abandoned",
+        }
+    }
+
+    /// The outcome with the given name, if any.
+    fn from_name(name: &str) -> Option<Outcome> {
+        match name {
+            "won" => Some(Outcome::Won),
+            "wiped" => Some(Outcome::Wiped),
+            "abandoned" => Some(Outcome::Abandoned),
+            _ => None,
+        }
+    }
+}
+
+/// The record of one finished game.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Score {
+    /// When the game finished, in seconds since the Unix
+    /// epoch.
+    pub time: u64,
+    /// Game seed.
+    pub seed: u64,
+    /// How the game ended.
+    pub outcome: Outcome,
+    /// Turns played.
+    pub turns: u64,
+    /// Names of the heroes who joined.
+    pub heroes: Vec<String>,
+    /// Heroes who escaped.
+    pub escaped: u64,
+    /// Monsters slain.
+    pub kills: u64,
+    /// Damage taken by the party.
+    pub damage: u64,
+}
+
+impl Score {
+    /// Score file line for this score, without the newline.
+    fn format(&self) -> String {
+        format!(
+            "{} {} {} {} {} {} {} {}",
+            self.time,
+            self.seed,
+            self.outcome.name(),
+            self.turns,
+            self.escaped,
+            self.kills,
+            self.damage,
+            self.heroes.join(","),
+        )
+    }
+
+    /// Parse a score file line.
+    fn parse(line: &str) -> Result<Score, String> {
+        // Names of heroes without accounts have spaces, so
+        // the names are the rest of the line.
+        let fields: Vec<&str> = line.trim().splitn(8, ' ').collect();
+        let [time, seed, outcome, turns, escaped, kills, damage, heroes] = fields[..] else {
+            return Err(format!("expected 8 fields, got {}", fields.len()));
+        };
+        Ok(Score {
+            time: number(time)?,
+            seed: number(seed)?,
+            outcome: Outcome::from_name(outcome)
+                .ok_or_else(|| format!("unknown outcome \"{}\"", outcome))?,
+            turns: number(turns)?,
+            escaped: number(escaped)?,
+            kills: number(kills)?,
+            damage: number(damage)?,
+            heroes: heroes.split(',').map(String::from).collect(),
+        })
+    }
+
+    /// One leaderboard line for this score.
+    fn show(&self) -> String {
+        format!(
+            "{:9} {:6} turns {:3} escaped {:4} kills {:5} damage  {}",
+            self.outcome.name(),
+            self.turns,
+            self.escaped,
+            self.kills,
+            self.damage,
+            self.heroes.join(", "),
+        )
+    }
+}
+
+/// Add a score to the configured score file.
+pub fn save_score(score: &Score) -> io::Result<()> {
+    let path = &config().score_file;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    // One write per line, so that games finishing together
+    // do not interleave.
+    file.write_all(format!("{}\n", score.format()).as_bytes())
+}
+
+/// Read every score from the given score file, oldest
+/// first. A missing file holds no scores. Bad lines are
+/// reported on standard error and skipped, so that one does
+/// not hide the rest.
+pub fn load_scores(path: &Path) -> io::Result<Vec<Score>> {
+    let text = read_or_empty(path)?;
+    let mut scores = Vec::new();
+    for (lineno, line) in content_lines(&text) {
+        match Score::parse(line) {
+            Ok(score) => scores.push(score),
+            Err(e) => eprintln!("{}", bad(path.display(), lineno, &e)),
+        }
+    }
+    Ok(scores)
+}
+
+/// Leaderboard text for the given scores, one line per
+/// string: the fastest wins, the most kills, and the most
+/// recent games.
+pub fn leaderboards(scores: &[Score]) -> Vec<String> {
+    if scores.is_empty() {
+        return vec!["no games played yet".to_string()];
+    }
+    let mut wins: Vec<&Score> = scores
+        .iter()
+        .filter(|score| score.outcome == Outcome::Won)
+        .collect();
+    wins.sort_by_key(|score| score.turns);
+    let mut kills: Vec<&Score> = scores.iter().collect();
+    kills.sort_by_key(|score| std::cmp::Reverse(score.kills));
+    let recent: Vec<&Score> = scores.iter().rev().collect();
+
+    let mut lines = Vec::new();
+    for (title, board) in [
+        ("fastest wins", wins),
+        ("most kills", kills),
+        ("recent games", recent),
+    ] {
+        lines.push(format!("{}:", title));
+        if board.is_empty() {
+            lines.push("   none yet".to_string());
+        }
+        for (i, score) in board.into_iter().take(LEADERBOARD_LEN).enumerate() {
+            lines.push(format!("  {}. {}", i + 1, score.show()));
+        }
+    }
+    lines
+}
+
+impl Game {
+    /// The score for this game, which should be over.
+    pub fn score(&self) -> Score {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_secs());
+        Score {
+            time,
+            seed: self.seed,
+            outcome: self.ending.unwrap_or(Outcome::Abandoned),
+            turns: self.turns,
+            heroes: self.heroes.clone(),
+            escaped: self.escaped,
+            kills: self.kills,
+            damage: self.damage_taken,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_score_lines_are_skipped() {
+        let score = Score {
+            time: 1700000000,
+            seed: 42,
+            outcome: Outcome::Won,
+            turns: 300,
+            escaped: 2,
+            kills: 7,
+            damage: 55,
+            heroes: vec!["bart".to_string(), "hero 2".to_string()],
+        };
+        let path = std::env::temp_dir().join(format!("one-way-out-{}.scores", std::process::id()));
+        let text = format!("# scores\n{}\n1 2 won bogus\n\n", score.format());
+        fs::write(&path, text).unwrap();
+        let scores = load_scores(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(scores.unwrap(), vec![score]);
+    }
+}
@@ -0,0 +1,332 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! Saving running games, and resuming them when the server
+//! starts again. Every so often the state of each running
+//! game is written to the configured save file, and at
+//! startup the games in it go back in the registry. The
+//! heroes of a resumed game are away until their players
+//! sign in again under the same names and join it.
+//!
+//! The save file is text: a `[name]` stanza for each game.
+//! In a stanza, `key = value` lines give the game's
+//! counters. Then come one `field` line (see
+//! [Field::snapshot]), a `hero` line naming each hero who
+//! has joined, a `player` line per hero still in the game
+//! (see [Player::snapshot]), a `mob` line per MOB (see
+//! [Mob::snapshot]), and the game's recording as in a
+//! replay file. Resuming a game adds an `away` entry to its
+//! recording, and a hero coming back adds a `back` entry,
+//! so a game that has been through restarts still replays
+//! exactly. Blank lines and `#` comments are ignored.
+
+use crate::*;
+
+use std::path::Path;
+use std::time::Duration;
+
+/// Saved-game text for an item the party may hold.
+fn item_code(item: Option<Item>) -> &'static str {
+    item.map_or("-", |item| item.code())
+}
+
+/// The item the party may hold, from its saved-game text.
+fn parse_item(code: &str) -> Result<Option<Item>, String> {
+    if code == "-" {
+        return Ok(None);
+    }
+    Item::from_code(code)
+        .map(Some)
+        .ok_or_else(|| format!("unknown item \"{}\"", code))
+}
+
+impl Game {
+    /// Saved-game text for the game. The players' messages
+    /// are not saved.
+    pub fn snapshot(&self) -> String {
+        let mut text = String::new();
+        text += &format!("seed = {}\n", self.seed);
+        text += &format!("rng = {}\n", self.rng.get_seed());
+        text += &format!("turns = {}\n", self.turns);
+        text += &format!("next_player_id = {}\n", self.next_player_id);
+        text += &format!("next_monster_id = {}\n", self.next_monster_id);
+        text += &format!("health = {}\n", self.health);
+        text += &format!("slot = {}\n", item_code(self.slot));
+        text += &format!("weapon = {}\n", item_code(self.weapon));
+        text += &format!("armor = {}\n", item_code(self.armor));
+        text += &format!("escaped = {}\n", self.escaped);
+        text += &format!("kills = {}\n", self.kills);
+        text += &format!("damage_taken = {}\n", self.damage_taken);
+        text += &format!("field {}\n", self.field.snapshot());
+        for name in &self.heroes {
+            text += &format!("hero {}\n", name);
+        }
+        for player in self.players.values() {
+            text += &format!("player {}\n", player.snapshot());
+        }
+        for mob in self.monsters.values() {
+            text += &format!("mob {}\n", mob.snapshot());
+        }
+        for record in &self.log {
+            text += &record.format();
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Restore one line of saved-game text into the game.
+    fn restore_line(&mut self, line: &str) -> Result<(), String> {
+        let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match kind {
+            "field" => self.field = Field::from_snapshot(rest)?,
+            "hero" => self.heroes.push(rest.to_string()),
+            "player" => {
+                let player = Player::from_snapshot(rest)?;
+                self.players.insert(player.id, player);
+            }
+            "mob" => {
+                let mob = Mob::from_snapshot(rest)?;
+                self.monsters.insert(mob.id, mob);
+            }
+            "join" | "act" | "away" | "back" => self.log.push(Record::parse(line)?),
+            _ => {
+                let (key, value) = line
+                    .split_once('=')
+                    .ok_or_else(|| format!("unknown record \"{}\"", kind))?;
+                let (key, value) = (key.trim(), value.trim());
+                let num = || number(value).map_err(|e| format!("{}: {}", key, e));
+                match key {
+                    "seed" => self.seed = num()?,
+                    "rng" => self.rng = Rng::with_seed(num()?),
+                    "turns" => self.turns = num()?,
+                    "next_player_id" => self.next_player_id = num()?,
+                    "next_monster_id" => self.next_monster_id = num()?,
+                    "health" => self.health = num()?,
+                    "slot" => self.slot = parse_item(value)?,
+                    "weapon" => self.weapon = parse_item(value)?,
+                    "armor" => self.armor = parse_item(value)?,
+                    "escaped" => self.escaped = num()?,
+                    "kills" => self.kills = num()?,
+                    "damage_taken" => self.damage_taken = num()?,
+                    _ => return Err(format!("unknown setting \"{}\"", key)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish restoring a saved game: put the heroes and
+    /// MOBs back on the field, find the boss, and mark every
+    /// hero away.
+    fn resume(&mut self) -> Result<(), String> {
+        if self.players.is_empty() {
+            return Err("no heroes".to_string());
+        }
+        let objects = self
+            .players
+            .values()
+            .map(|player| {
+                (
+                    player.id,
+                    self.next_player_id,
+                    player.posn,
+                    Object::Player(player.id),
+                )
+            })
+            .chain(
+                self.monsters
+                    .values()
+                    .map(|mob| (mob.id, self.next_monster_id, mob.posn, mob.object())),
+            );
+        for (id, next_id, posn, object) in objects {
+            if id >= next_id {
+                return Err(format!("id {} is not yet given out", id));
+            }
+            if self.field.has_object(posn) || posn >= self.field.len() {
+                return Err(format!("cannot place {} at {}", object.render(), posn));
+            }
+            self.field[posn].object = Some(object);
+        }
+        self.boss = self
+            .monsters
+            .values()
+            .find(|mob| mob.boss)
+            .map(|mob| mob.id);
+        self.send_away();
+        Ok(())
+    }
+}
+
+/// Read the saved games from the given file, with their
+/// names. A missing file holds no games.
+pub fn load_games(path: &Path) -> io::Result<Vec<(String, Game)>> {
+    parse_games(path, &read_or_empty(path)?)
+}
+
+/// Read saved games from the text of the save file at
+/// `path`.
+fn parse_games(path: &Path, text: &str) -> io::Result<Vec<(String, Game)>> {
+    let mut games: Vec<(String, Game)> = Vec::new();
+    // Finish restoring the last game read, if any.
+    let resume_last = |games: &mut Vec<(String, Game)>, lineno: usize| match games.last_mut() {
+        Some((name, game)) => game
+            .resume()
+            .map_err(|e| bad(path.display(), lineno, &format!("{}: {}", name, e))),
+        None => Ok(()),
+    };
+    for (lineno, line) in content_lines(text) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let name = name.trim();
+            resume_last(&mut games, lineno)?;
+            if games.iter().any(|(other, _)| other == name) {
+                return Err(bad(path.display(), lineno, "duplicate game"));
+            }
+            games.push((name.to_string(), Game::empty(0)));
+            continue;
+        }
+        let Some((_, game)) = games.last_mut() else {
+            return Err(bad(path.display(), lineno, "line outside of a [game]"));
+        };
+        game.restore_line(line)
+            .map_err(|e| bad(path.display(), lineno, &e))?;
+    }
+    resume_last(&mut games, text.lines().count())?;
+    Ok(games)
+}
+
+/// Save the registry's running games to the given file
+/// every [ServerConfig::save_interval] seconds, forever.
+pub fn autosave(registry: GameRegistry, path: &Path) {
+    let interval = Duration::from_secs(config().save_interval);
+    loop {
+        std::thread::sleep(interval);
+        if let Err(e) = replace_file(path, &registry.snapshot()) {
+            eprintln!("cannot save games: {}", e);
+        }
+    }
+}
+
+impl GameHandle {
+    /// A handle for a resumed game.
+    pub fn resume(game: Game) -> Self {
+        GameHandle(Arc::new(Mutex::new(game)))
+    }
+
+    /// Saved-game text for the game, or `None` if it is
+    /// over.
+    pub fn snapshot(&self) -> Option<String> {
+        let game = self.lock();
+        (!game.over).then(|| game.snapshot())
+    }
+
+    /// Is the named player's hero away in this game?
+    pub fn is_waiting(&self, name: &str) -> bool {
+        self.lock().away_hero(name).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A two-hero game from a fixed seed, with the players'
+    /// IDs, and the same game saved and loaded back.
+    fn save_and_load() -> (Game, u64, u64, Game) {
+        let mut game = Game::new(99);
+        let alice = game.join(Some("alice"), Some(40)).unwrap();
+        let bob = game.join(Some("bob"), Some(40)).unwrap();
+        for _ in 0..20 {
+            game.apply(alice, Action::Move(Dir::Right));
+            game.apply(bob, Action::Rest);
+        }
+        assert!(!game.over);
+        let text = format!("[saved]\n{}", game.snapshot());
+        let games = parse_games(Path::new("test.save"), &text);
+        let (name, loaded) = games.unwrap().pop().unwrap();
+        assert_eq!(name, "saved");
+        (game, alice, bob, loaded)
+    }
+
+    #[test]
+    fn games_survive_a_round_trip() {
+        let (game, alice, bob, mut loaded) = save_and_load();
+        let away = Record {
+            turn: game.turns,
+            player_id: 0,
+            entry: Entry::Away,
+        };
+        assert_eq!(loaded.log.pop(), Some(away));
+        assert_eq!(loaded.snapshot(), game.snapshot());
+        assert_eq!(loaded.boss, game.boss);
+        assert_eq!(loaded.away, BTreeSet::from([alice, bob]));
+        assert_eq!(loaded.heroes, ["alice", "bob"]);
+    }
+
+    #[test]
+    fn narrow_views_load() {
+        // A view narrower than the margins, as resizing can
+        // leave it.
+        let player = Player::from_snapshot("1 30 8 12 hero 1").unwrap();
+        assert_eq!((player.posn, player.left, player.width), (30, 12, 8));
+        let player = Player::from_snapshot("1 3 40 12 hero 1").unwrap();
+        assert_eq!(player.left, 3);
+    }
+
+    #[test]
+    fn away_heroes_sit_out() {
+        let (_, alice, bob, mut loaded) = save_and_load();
+
+        // A monster next to away Alice does her no harm.
+        let posn = loaded.players[&alice].posn + 1;
+        if !matches!(loaded.field[posn].object, Some(Object::Monster(..))) {
+            let id = loaded.next_monster_id;
+            loaded.next_monster_id += 1;
+            let mob = Mob::new(id, posn, &kinds()[0], &mut loaded.rng);
+            loaded.field[posn].object = Some(mob.object());
+            loaded.monsters.insert(id, mob);
+        }
+        assert_eq!(loaded.monster_attacks(), 0);
+
+        // Bob comes back and quits, but Alice is still away.
+        assert_eq!(loaded.join(Some("bob"), Some(40)), Some(bob));
+        assert_eq!(loaded.apply(bob, Action::Quit), [GameEvent::Quit]);
+        assert!(!loaded.over);
+        assert_eq!(loaded.ending, None);
+    }
+
+    #[test]
+    fn resumed_games_replay_exactly() {
+        let (_, alice, bob, mut loaded) = save_and_load();
+        assert_eq!(loaded.join(Some("bob"), Some(30)), Some(bob));
+        for _ in 0..10 {
+            loaded.apply(bob, Action::Move(Dir::Right));
+        }
+        let text = format!("[saved]\n{}", loaded.snapshot());
+        let (_, mut loaded) = parse_games(Path::new("test.save"), &text)
+            .unwrap()
+            .pop()
+            .unwrap();
+        assert_eq!(loaded.join(Some("alice"), None), Some(alice));
+        loaded.apply(alice, Action::Rest);
+
+        let mut replayed = Game::new(loaded.seed);
+        for record in &loaded.log {
+            replay_record(&mut replayed, record).unwrap();
+        }
+        // The replay names its heroes differently, but all
+        // else is the same.
+        let state = |game: &Game| {
+            let text = game.snapshot();
+            let lines: Vec<String> = text
+                .lines()
+                .filter(|line| !line.starts_with("hero ") && !line.starts_with("player "))
+                .map(str::to_string)
+                .collect();
+            let posns: Vec<usize> = game.players.values().map(|p| p.posn).collect();
+            (lines, posns, game.away.clone())
+        };
+        assert_eq!(state(&replayed), state(&loaded));
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! Spectator mode: watch a game in progress through the
+//! eyes of one of its heroes. A spectator has no avatar, so
+//! it takes no place on the field and does not count as a
+//! player.
+
+use crate::*;
+
+/// Which hero a spectator is watching.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Follow {
+    /// Whichever hero is furthest along.
+    Leader,
+    /// The hero with this player ID.
+    Hero(u64),
+}
+
+impl Game {
+    /// Player ID of the hero being followed. There must be
+    /// at least one hero in the game.
+    fn followed(&self, follow: Follow) -> u64 {
+        match follow {
+            Follow::Hero(id) if self.players.contains_key(&id) => id,
+            _ => self.leader().unwrap(),
+        }
+    }
+
+    /// Player ID of the hero after (`dirn` 1) or before
+    /// (`dirn` -1) the given one in ID order, wrapping
+    /// around. There must be at least one hero in the game.
+    fn cycle_hero(&self, player_id: u64, dirn: isize) -> u64 {
+        let ids: Vec<u64> = self.players.keys().copied().collect();
+        let n = ids.len() as isize;
+        let i = ids.iter().position(|&id| id == player_id).unwrap_or(0) as isize;
+        ids[(i + dirn).rem_euclid(n) as usize]
+    }
+}
+
+impl GameHandle {
+    /// The spectator loop for a client watching this game.
+    /// `h` and `l` or the arrow keys cycle between heroes,
+    /// `f` follows the leader, and `q` stops watching. Returns `false` if
+    /// the remote connection was lost.
+    pub fn watch(mut self, remote: &mut Connection) -> bool {
+        let _ = write!(
+            remote,
+            "\rwatching: h/l change hero, f follow leader, q stop\r\n",
+        );
+        let mut follow = Follow::Leader;
+        let mut watched = None;
+        let (mut area, mut width) = StatusArea::layout(remote);
+        let mut screen = screen_for(remote, area);
+        let mut keys = KeyBuffer::default();
+        loop {
+            match remote.read() {
+                Ok(Some(Input::Keys(data))) => keys.push(&data),
+                Ok(Some(Input::Resize)) => {
+                    (area, width) = StatusArea::layout(remote);
+                    screen = screen_for(remote, area);
+                }
+                Ok(None) => keys.timed_out(),
+                Err(e) => {
+                    eprintln!("net read error: {}", e);
+                    return false;
+                }
+            }
+
+            // Act on spectator commands and render the view.
+            let done = self.with_game(|game| {
+                if game.over || game.players.is_empty() {
+                    screen.finish(remote, "game over");
+                    return true;
+                }
+                while let Some(key) = keys.next_key() {
+                    let current = game.followed(follow);
+                    match key {
+                        Key::Char('q') => {
+                            screen.finish(remote, "stopped watching");
+                            return true;
+                        }
+                        Key::Char('f') => follow = Follow::Leader,
+                        Key::Char('h') | Key::Left => {
+                            follow = Follow::Hero(game.cycle_hero(current, -1))
+                        }
+                        Key::Char('l') | Key::Right => {
+                            follow = Follow::Hero(game.cycle_hero(current, 1))
+                        }
+                        _ => (),
+                    }
+                }
+                let current = game.followed(follow);
+
+                // Announce a change of hero.
+                let leading = follow == Follow::Leader;
+                if watched != Some((current, leading)) {
+                    let note = if leading { " (leader)" } else { "" };
+                    screen.print(remote, &format!("watching {}{}", game.name(current), note));
+                    watched = Some((current, leading));
+                }
+
+                // Send the hero's view, cut or padded to our
+                // width, with their status.
+                let (render, cursor) = game.view(current).unwrap();
+                let width = width as usize;
+                let render = format!("{:width$.width$}", render);
+                let mut tiles = game.tiles(current).unwrap();
+                tiles.resize(width, Tile::Blank);
+                let cursor = cursor.min(width);
+                let compact = matches!(area, StatusArea::Segment(_));
+                let status = game.status(current, compact).unwrap();
+                screen.draw(
+                    remote,
+                    Showing::Board(render, tiles, None),
+                    &status,
+                    cursor,
+                    false,
+                );
+                false
+            });
+            if done {
+                return true;
+            }
+        }
+    }
+}
@@ -0,0 +1,39 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! The status display: the party's health, the game clock,
+//! how far the player has to go, how many heroes are left,
+//! and the boss's health once it is in sight.
+
+use crate::*;
+
+impl Game {
+    /// Status display text for the given player. If
+    /// `compact`, it is abbreviated to fit in a segment of
+    /// the board line, most important first. Returns `None`
+    /// if the player is not in the game.
+    pub fn status(&self, player_id: u64, compact: bool) -> Option<String> {
+        let player = self.players.get(&player_id)?;
+        let max_health = config().max_health;
+        let door = config().door_posn.saturating_sub(player.posn);
+        let heroes = self.players.len();
+        let boss = self.boss_status(player_id);
+        if compact {
+            let boss = boss.map_or(String::new(), |(health, _)| format!(" B{}", health));
+            return Some(format!(
+                "{}/{}{} d{} h{} t{}",
+                self.health, max_health, boss, door, heroes, self.turns,
+            ));
+        }
+        let mut status = format!(
+            "health {}/{}  turn {}  door {}  heroes {}",
+            self.health, max_health, self.turns, door, heroes,
+        );
+        if let Some((health, max_health)) = boss {
+            status += &format!("  boss {}/{}", health, max_health);
+        }
+        Some(status)
+    }
+}
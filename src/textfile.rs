@@ -0,0 +1,74 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! Shared pieces of the server's text file formats: the
+//! config file, the monster table, replays, saved games,
+//! scores and accounts. Each is read a line at a time, with
+//! `#` comments and blank lines ignored.
+
+use crate::*;
+
+use std::fmt::Display;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The lines of `text` that hold something, with their
+/// zero-based line numbers. `#` comments are cut off, the
+/// rest of each line is trimmed, and blank lines are
+/// skipped.
+pub fn content_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .map(|line| line.find('#').map_or(line, |i| &line[..i]).trim())
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+}
+
+/// Make a file format error at the given zero-based line of
+/// the file at `path`.
+pub fn bad(path: impl Display, lineno: usize, msg: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("{}:{}: {}", path, lineno + 1, msg),
+    )
+}
+
+/// Parse a number field.
+pub fn number<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid number \"{}\"", value))
+}
+
+/// The text of the file at `path`. A missing file reads as
+/// empty.
+pub fn read_or_empty(path: &Path) -> io::Result<String> {
+    match fs::read_to_string(path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+        result => result,
+    }
+}
+
+/// Write `text` to the file at `path`. The file is replaced
+/// whole, so that a crash cannot leave it half written.
+pub fn replace_file(path: &Path, text: &str) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, text)?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments_and_blanks_are_skipped() {
+        let text = "# header\n\n  a = 1  # note\n   \nb\n#\n";
+        let lines: Vec<(usize, &str)> = content_lines(text).collect();
+        assert_eq!(lines, [(2, "a = 1"), (4, "b")]);
+    }
+}
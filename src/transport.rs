@@ -0,0 +1,362 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! Byte-stream transports that a [Connection] can run over,
+//! and the listeners that accept them. Anything that can
+//! read, write and time out a read can carry the telnet
+//! protocol: TCP and Unix-domain sockets are provided here,
+//! and in-memory pipes for tests.
+
+use crate::*;
+
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::{
+    fs::FileTypeExt,
+    net::{UnixListener, UnixStream},
+};
+use std::path::Path;
+use std::time::Duration;
+
+/// A duplex byte stream that can carry a [Connection].
+pub trait Duplex: Read + Write + Send + Sync {
+    /// Set how long a read may block before failing with
+    /// [io::ErrorKind::WouldBlock] or
+    /// [io::ErrorKind::TimedOut]. `None` blocks forever.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Make reads fail with [io::ErrorKind::WouldBlock]
+    /// rather than block when no data is available.
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Duplex for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Duplex for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+/// Bytes in flight one way through a [Pipe].
+#[cfg(test)]
+#[derive(Default)]
+struct Channel {
+    /// Bytes written and not yet read, and whether the
+    /// writer has gone.
+    data: Mutex<(std::collections::VecDeque<u8>, bool)>,
+    /// Signalled when bytes arrive or the writer goes away.
+    ready: std::sync::Condvar,
+}
+
+/// One end of an in-memory duplex pipe, standing in for a
+/// socket in tests. Bytes written to one end are read from
+/// the other.
+#[cfg(test)]
+pub struct Pipe {
+    /// Channel this end reads from.
+    incoming: Arc<Channel>,
+    /// Channel this end writes to.
+    outgoing: Arc<Channel>,
+    /// Read timeout, as for a socket.
+    timeout: Mutex<Option<Duration>>,
+    /// Reads fail rather than block.
+    nonblocking: std::sync::atomic::AtomicBool,
+}
+
+#[cfg(test)]
+impl Pipe {
+    /// Make a connected pair of pipe ends.
+    pub fn pair() -> (Pipe, Pipe) {
+        let (a, b) = (Arc::new(Channel::default()), Arc::new(Channel::default()));
+        let end = |incoming: &Arc<Channel>, outgoing: &Arc<Channel>| Pipe {
+            incoming: incoming.clone(),
+            outgoing: outgoing.clone(),
+            timeout: Mutex::new(None),
+            nonblocking: Default::default(),
+        };
+        (end(&a, &b), end(&b, &a))
+    }
+}
+
+#[cfg(test)]
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use std::sync::atomic::Ordering;
+        let timeout = *self.timeout.lock().unwrap();
+        let nonblocking = self.nonblocking.load(Ordering::Relaxed);
+        let mut data = self.incoming.data.lock().unwrap();
+        while data.0.is_empty() {
+            // End of stream once the writer has gone.
+            if data.1 {
+                return Ok(0);
+            }
+            if nonblocking {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            data = match timeout {
+                None => self.incoming.ready.wait(data).unwrap(),
+                Some(timeout) => {
+                    let (data, wait) = self.incoming.ready.wait_timeout(data, timeout).unwrap();
+                    if wait.timed_out() && data.0.is_empty() {
+                        return Err(io::ErrorKind::WouldBlock.into());
+                    }
+                    data
+                }
+            };
+        }
+        let n = buf.len().min(data.0.len());
+        for (b, byte) in buf.iter_mut().zip(data.0.drain(..n)) {
+            *b = byte;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outgoing.data.lock().unwrap().0.extend(buf);
+        self.outgoing.ready.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        self.outgoing.data.lock().unwrap().1 = true;
+        self.outgoing.ready.notify_all();
+    }
+}
+
+#[cfg(test)]
+impl Duplex for Pipe {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        *self.timeout.lock().unwrap() = timeout;
+        Ok(())
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        use std::sync::atomic::Ordering;
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Adapter presenting a boxed [Duplex] as a telnet stream.
+pub(crate) struct Transport(pub Box<dyn Duplex>);
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl telnet::Stream for Transport {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.0.set_nonblocking(nonblocking)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.0.set_read_timeout(timeout)
+    }
+}
+
+/// Hand an accepted stream to its own client thread, which
+/// sets up the connection and runs the lobby.
+fn spawn_client(registry: &GameRegistry, stream: Box<dyn Duplex>) {
+    let registry = registry.clone();
+    let _ = std::thread::spawn(move || {
+        if let Some(conn) = Connection::new(stream).setup() {
+            registry.lobby(conn);
+        }
+    });
+}
+
+/// Listen for TCP client connections on the configured
+/// address and send them to the lobby of the given game
+/// registry. Fails only if the address cannot be bound.
+pub fn listen_tcp(registry: GameRegistry) -> io::Result<()> {
+    let config = config();
+    let listener = TcpListener::bind((config.bind, config.port))?;
+    loop {
+        match listener.accept() {
+            Ok((socket, addr)) => {
+                println!("new client: {:?}", addr);
+                spawn_client(&registry, Box::new(socket));
+            }
+            Err(e) => {
+                println!("couldn't get client: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Bind a Unix-domain socket at `path`. A stale socket left
+/// there by an earlier run is replaced, but a socket that a
+/// running server still answers on is left alone, and
+/// binding fails with [io::ErrorKind::AddrInUse].
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> io::Result<UnixListener> {
+    if let Ok(meta) = std::fs::symlink_metadata(path)
+        && meta.file_type().is_socket()
+    {
+        match UnixStream::connect(path) {
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "socket is in use by another server",
+                ));
+            }
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                std::fs::remove_file(path)?;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    UnixListener::bind(path)
+}
+
+/// Listen for Unix-domain socket client connections at
+/// `path` and send them to the lobby of the given game
+/// registry. A stale socket left at `path` by an earlier
+/// run is replaced. Fails only if the socket cannot be
+/// bound, as when another server is listening on it.
+#[cfg(unix)]
+pub fn listen_unix(registry: GameRegistry, path: &Path) -> io::Result<()> {
+    let listener = bind_unix(path)?;
+    loop {
+        match listener.accept() {
+            Ok((socket, _)) => {
+                println!("new client: {}", path.display());
+                spawn_client(&registry, Box::new(socket));
+            }
+            Err(e) => {
+                println!("couldn't get client: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Unix-domain sockets are not available on this platform.
+#[cfg(not(unix))]
+pub fn listen_unix(_registry: GameRegistry, path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{}: Unix-domain sockets are not supported", path.display()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Telnet IAC, DO and WILL, and the SUPPRESS-GO-AHEAD
+    /// option.
+    const IAC: u8 = 255;
+    const DO: u8 = 253;
+    const WILL: u8 = 251;
+    const SGA: u8 = 3;
+
+    #[test]
+    fn connection_runs_over_a_pipe() {
+        let (server, mut client) = Pipe::pair();
+        let mut remote = Connection::new(Box::new(server));
+
+        client.write_all(&[IAC, DO, SGA]).unwrap();
+        assert!(remote.negotiate_cbreak().unwrap());
+        let mut sent = [0; 3];
+        client.read_exact(&mut sent).unwrap();
+        assert_eq!(sent, [IAC, WILL, SGA]);
+
+        client.write_all(b"hello\r\n").unwrap();
+        assert_eq!(remote.read_line().unwrap(), "hello");
+    }
+
+    #[test]
+    fn refused_cbreak_means_line_mode() {
+        const WONT: u8 = 252;
+        const DONT: u8 = 254;
+        const ECHO: u8 = 1;
+        const NAWS: u8 = 31;
+        const NEW_ENVIRON: u8 = 39;
+        let (server, mut client) = Pipe::pair();
+        // Refuse window size, cbreak and user name, but let
+        // the server echo.
+        client
+            .write_all(&[
+                IAC,
+                WONT,
+                NAWS,
+                IAC,
+                DONT,
+                SGA,
+                IAC,
+                DO,
+                ECHO,
+                IAC,
+                WONT,
+                NEW_ENVIRON,
+            ])
+            .unwrap();
+        let remote = Connection::new(Box::new(server)).setup().unwrap();
+        assert!(!remote.echo);
+        assert!(remote.line_mode);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn live_unix_sockets_are_kept() {
+        let path = std::env::temp_dir().join(format!("one-way-out-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let live = bind_unix(&path).unwrap();
+        let err = bind_unix(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        // Once the server has gone its socket is stale.
+        drop(live);
+        let rebound = bind_unix(&path);
+        let _ = std::fs::remove_file(&path);
+        rebound.unwrap();
+    }
+
+    #[test]
+    fn pipe_read_times_out() {
+        let (mut end, _other) = Pipe::pair();
+        end.set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        let err = end.read(&mut [0; 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+}
@@ -0,0 +1,211 @@
+// Copyright © 2019 Bart Massey
+// [This program is licensed under the GPL version 3 or later.]
+// Please see the file LICENSE in the source
+// distribution of this software for license terms.
+
+//! WebSocket gateway, so that browsers can play without a
+//! telnet client. A single port serves both a small static
+//! page and the WebSocket it connects back to. The socket
+//! carries exactly the bytes a telnet client would see, so
+//! browser players run through the same [Connection] and
+//! lobby as everyone else, and share the same games.
+
+use crate::*;
+
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use tungstenite::{Message, WebSocket, handshake::derive_accept_key, protocol::Role};
+
+/// The browser client page.
+const INDEX_HTML: &str = include_str!("web.html");
+
+/// Longest HTTP request header section accepted, in bytes.
+const MAX_REQUEST: usize = 8192;
+
+/// How long a browser has to send its HTTP request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A WebSocket presented as a byte stream. Incoming message
+/// payloads are concatenated; each write is sent as one
+/// binary message.
+struct WsStream {
+    /// The socket.
+    ws: WebSocket<TcpStream>,
+    /// Unread bytes from the last incoming message.
+    pending: Vec<u8>,
+}
+
+/// Convert a WebSocket error into an IO error.
+fn ws_io_error(e: tungstenite::Error) -> io::Error {
+    match e {
+        tungstenite::Error::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+impl Read for WsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() {
+            match self.ws.read() {
+                Ok(Message::Text(text)) => self.pending = text.as_bytes().to_vec(),
+                Ok(Message::Binary(data)) => self.pending = data.to_vec(),
+                Ok(Message::Close(_))
+                | Err(tungstenite::Error::ConnectionClosed)
+                | Err(tungstenite::Error::AlreadyClosed) => return Ok(0),
+                Ok(_) => (),
+                Err(e) => return Err(ws_io_error(e)),
+            }
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for WsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.ws
+            .send(Message::Binary(buf.to_vec().into()))
+            .map_err(ws_io_error)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.ws.flush().map_err(ws_io_error)
+    }
+}
+
+impl Duplex for WsStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.ws.get_ref().set_read_timeout(timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.ws.get_ref().set_nonblocking(nonblocking)
+    }
+}
+
+/// The parts of an HTTP request the gateway cares about.
+struct Request {
+    /// Request path, including any query string.
+    path: String,
+    /// `Sec-WebSocket-Key` header, if this is an upgrade.
+    ws_key: Option<String>,
+}
+
+/// Read an HTTP request line and headers.
+fn read_request(stream: &TcpStream) -> io::Result<Request> {
+    let bad = |msg: &str| io::Error::new(ErrorKind::InvalidData, msg.to_string());
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST as u64));
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut words = line.split_whitespace();
+    if words.next() != Some("GET") {
+        return Err(bad("expected GET request"));
+    }
+    let path = words.next().ok_or_else(|| bad("missing path"))?.to_string();
+    let mut ws_key = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(bad("truncated request"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("sec-websocket-key")
+        {
+            ws_key = Some(value.trim().to_string());
+        }
+    }
+    Ok(Request { path, ws_key })
+}
+
+/// Get the terminal width requested by the page as a
+/// `cols` query parameter.
+fn query_width(path: &str) -> Option<u16> {
+    let (_, query) = path.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|param| param.strip_prefix("cols="))
+        .find_map(|cols| cols.parse().ok())
+        .filter(|&cols| cols > 0)
+}
+
+/// Answer one HTTP client: serve the page, or upgrade to a
+/// WebSocket and run the lobby over it.
+fn serve(registry: GameRegistry, mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let request = read_request(&stream)?;
+    let Some(key) = request.ws_key else {
+        let (status, body) = match request.path.as_str() {
+            "/" | "/index.html" => ("200 OK", INDEX_HTML),
+            _ => ("404 Not Found", "not found\n"),
+        };
+        let content_type = if status.starts_with("200") {
+            "text/html; charset=utf-8"
+        } else {
+            "text/plain"
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body,
+        )?;
+        return stream.flush();
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.as_bytes()),
+    )?;
+    stream.flush()?;
+    let ws = WebSocket::from_raw_socket(stream, Role::Server, None);
+    let mut conn = Connection::new(Box::new(WsStream {
+        ws,
+        pending: Vec::new(),
+    }));
+    // The page sends each keystroke as typed and does not
+    // echo, so no telnet negotiation is needed.
+    conn.cbreak = true;
+    conn.echo = false;
+    conn.width = query_width(&request.path);
+    conn.set_timeout(Some(100));
+    registry.lobby(conn);
+    Ok(())
+}
+
+/// Listen for browser connections on the configured web
+/// port and send WebSocket players to the lobby of the given
+/// game registry. Fails only if the port cannot be bound.
+pub fn listen_web(registry: GameRegistry, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind((config().bind, port))?;
+    loop {
+        match listener.accept() {
+            Ok((socket, addr)) => {
+                println!("new web client: {:?}", addr);
+                let registry = registry.clone();
+                let _ = std::thread::spawn(move || {
+                    if let Err(e) = serve(registry, socket) {
+                        eprintln!("web client error: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                println!("couldn't get client: {:?}", e);
+            }
+        }
+    }
+}